@@ -1,5 +1,5 @@
 use core::panic;
-use std::{collections::HashSet, fmt::Debug, fs, ops::DerefMut};
+use std::{collections::HashSet, fmt::Debug, fs};
 
 fn todigit(i: &u8) -> u8 {
     i - 48
@@ -1,6 +1,8 @@
 use crate::util::count_digits;
-use std::{collections::HashMap, fs, hash::Hash};
+use std::{collections::HashMap, fs};
 
+// `usize::is_multiple_of` needs Rust 1.87, so keep the plain modulo
+#[allow(clippy::manual_is_multiple_of)]
 pub fn get_blink_splits(
     orig_num: usize,
     blinks: usize,
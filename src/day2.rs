@@ -1,6 +1,94 @@
 use proptest::proptest;
 use std::fs;
 
+/// Describes what makes a report "safe".
+///
+/// Every step between two kept levels has to change by at least `min_step` and at most
+/// `max_step`. If `strictly_monotonic` is set, all steps also have to go in the same direction
+/// (so a step of 0 is never allowed). Up to `removable_levels` levels can be dropped from a
+/// report to make it pass.
+#[derive(Clone, Copy, Debug)]
+pub struct SafetyRules {
+    pub min_step: i32,
+    pub max_step: i32,
+    pub strictly_monotonic: bool,
+    pub removable_levels: usize,
+}
+
+impl Default for SafetyRules {
+    // The rules from the puzzle, without the problem dampener
+    fn default() -> Self {
+        SafetyRules {
+            min_step: 1,
+            max_step: 3,
+            strictly_monotonic: true,
+            removable_levels: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Trend {
+    Increasing,
+    Decreasing,
+    Any,
+}
+
+impl SafetyRules {
+    fn step_allowed(&self, from: i32, to: i32, trend: Trend) -> bool {
+        let diff = i64::from(to) - i64::from(from);
+        let size_ok = (i64::from(self.min_step)..=i64::from(self.max_step)).contains(&diff.abs());
+        match trend {
+            Trend::Increasing => diff > 0 && size_ok,
+            Trend::Decreasing => diff < 0 && size_ok,
+            Trend::Any => size_ok,
+        }
+    }
+
+    fn removals_needed_for_trend(&self, report: &[i32], trend: Trend) -> usize {
+        let window = self.removable_levels + 1;
+        // removals[i] is the fewest levels we have to remove from report[..=i] so that what's
+        // left is valid and ends with report[i] kept
+        let mut removals: Vec<usize> = Vec::with_capacity(report.len());
+        for (i, &level) in report.iter().enumerate() {
+            // We can always drop everything before i and start fresh from here
+            let mut best = i;
+            // Only look back far enough that the gap between the two kept levels could still be
+            // removed, which keeps this O(n * k)
+            for j in i.saturating_sub(window)..i {
+                if self.step_allowed(report[j], level, trend) {
+                    best = best.min(removals[j] + (i - j - 1));
+                }
+            }
+            removals.push(best);
+        }
+        // Anything after the last kept level has to be removed as well
+        removals
+            .iter()
+            .enumerate()
+            .map(|(i, r)| r + (report.len() - 1 - i))
+            .min()
+            .unwrap_or(0)
+    }
+
+    // Returns the fewest number of levels that have to be removed from `report` for it to follow
+    // these rules. Only exact up to `removable_levels`, past that it's just some bigger number
+    fn removals_needed(&self, report: &[i32]) -> usize {
+        if self.strictly_monotonic {
+            usize::min(
+                self.removals_needed_for_trend(report, Trend::Increasing),
+                self.removals_needed_for_trend(report, Trend::Decreasing),
+            )
+        } else {
+            self.removals_needed_for_trend(report, Trend::Any)
+        }
+    }
+
+    pub fn is_safe(&self, report: &[i32]) -> bool {
+        self.removals_needed(report) <= self.removable_levels
+    }
+}
+
 fn parse_input(input: &str) -> Vec<Vec<i32>> {
     input
         .lines()
@@ -12,64 +100,23 @@ fn parse_input(input: &str) -> Vec<Vec<i32>> {
         .collect::<Vec<_>>()
 }
 
-fn test_is_safe(test: &[i32], dampen: bool) -> bool {
-    let mut increased = false;
-    let mut decreased = false;
-    // Skip the first item, we only want items where we have a previous item to compare to
-    if dampen {
-        return (0..test.len())
-            .any(|i| test_is_safe(&[&test[0..i], &test[(i + 1)..test.len()]].concat(), false));
-    }
-    for (i, num) in test[1..].iter().enumerate() {
-        let last = test[i];
-        let diff = last - num;
-        match diff {
-            // No change or too large change - not safe
-            0 | ..=-4 | 4.. => return false,
-
-            // Decreased within bounds
-            -3..0 => {
-                // If we've previously increased, it's not safe
-                if increased {
-                    return false;
-                } else {
-                    decreased = true;
-                }
-            }
-            1..=3 => {
-                if decreased {
-                    return false;
-                } else {
-                    increased = true;
-                }
-            }
-        }
-    }
-    // If we didn't hit any of the unsafe conditions and return early, it's safe
-    true
+fn count_safe(data: &[Vec<i32>], rules: &SafetyRules) -> usize {
+    data.iter()
+        .filter(|t| !t.is_empty())
+        .filter(|t| rules.is_safe(t))
+        .count()
 }
 
 pub fn solve(raw_data: &str) {
     let data = parse_input(raw_data);
-    let safe_count: i32 = data
-        .iter()
-        .filter(|t| !t.is_empty())
-        .map(|t| test_is_safe(t, false))
-        .map(|res| match res {
-            true => 1,
-            false => 0,
-        })
-        .sum();
+    let rules = SafetyRules::default();
+    let safe_count = count_safe(&data, &rules);
     println!("{} tests are safe without dampener", safe_count);
-    let safe_count_with_dampener: i32 = data
-        .iter()
-        .filter(|t| !t.is_empty())
-        .map(|t| test_is_safe(t, true))
-        .map(|res| match res {
-            true => 1,
-            false => 0,
-        })
-        .sum();
+    let dampened_rules = SafetyRules {
+        removable_levels: 1,
+        ..rules
+    };
+    let safe_count_with_dampener = count_safe(&data, &dampened_rules);
     println!("{} tests are safe with dampener", safe_count_with_dampener);
 }
 
@@ -78,9 +125,77 @@ pub fn solution() {
     solve(&raw_data);
 }
 
+// Tries every way of removing up to `removable_levels` levels, used to check the fast version
+#[cfg(test)]
+fn brute_force_is_safe(test: &[i32], rules: &SafetyRules) -> bool {
+    let steps_ok = |trend| {
+        test.windows(2)
+            .all(|pair| rules.step_allowed(pair[0], pair[1], trend))
+    };
+    let passes = if rules.strictly_monotonic {
+        steps_ok(Trend::Increasing) || steps_ok(Trend::Decreasing)
+    } else {
+        steps_ok(Trend::Any)
+    };
+    if passes {
+        return true;
+    }
+    if rules.removable_levels == 0 {
+        return false;
+    }
+    let fewer_removals = SafetyRules {
+        removable_levels: rules.removable_levels - 1,
+        ..*rules
+    };
+    (0..test.len()).any(|i| {
+        brute_force_is_safe(
+            &[&test[0..i], &test[(i + 1)..test.len()]].concat(),
+            &fewer_removals,
+        )
+    })
+}
+
+#[test]
+fn example_reports() {
+    let rules = SafetyRules::default();
+    let dampened = SafetyRules {
+        removable_levels: 1,
+        ..rules
+    };
+    let reports = [
+        (vec![7, 6, 4, 2, 1], true, true),
+        (vec![1, 2, 7, 8, 9], false, false),
+        (vec![9, 7, 6, 2, 1], false, false),
+        (vec![1, 3, 2, 4, 5], false, true),
+        (vec![8, 6, 4, 4, 1], false, true),
+        (vec![1, 3, 6, 7, 9], true, true),
+    ];
+    for (report, safe, safe_dampened) in reports {
+        assert_eq!(rules.is_safe(&report), safe, "{:?}", report);
+        assert_eq!(dampened.is_safe(&report), safe_dampened, "{:?}", report);
+    }
+}
+
 proptest! {
     #[test]
     fn it_works(raw_data in "(([0-9]{1, 6} )*[0-9]{1, 6}\\n)+") {
         solve(&raw_data);
     }
+
+    #[test]
+    fn matches_brute_force(
+        report in proptest::collection::vec(0..12_i32, 0..8),
+        min_step in 0..3_i32,
+        extra_step in 0..3_i32,
+        strictly_monotonic: bool,
+        removable_levels in 0..3_usize,
+    ) {
+        let rules = SafetyRules {
+            min_step,
+            max_step: min_step + extra_step,
+            strictly_monotonic,
+            removable_levels,
+        };
+        assert_eq!(rules.is_safe(&report), brute_force_is_safe(&report, &rules));
+    }
 }
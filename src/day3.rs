@@ -1,151 +1,536 @@
-use std::fs;
+use crate::util::{bench, has_flag, ANSI_GREEN, ANSI_RED, ANSI_RESET, ANSI_YELLOW};
+use proptest::proptest;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::ops::Range;
 
-enum MulState {
-    Seeking,
-    M,
-    U,
-    L,
-    FirstNumber(Vec<char>),
-    SecondNumber(Vec<char>, Vec<char>),
+/// State that instructions act on while the program is being interpreted.
+#[derive(Debug)]
+pub struct Machine {
+    pub total: i64,
+    pub enabled: bool,
 }
 
-enum ConditionalState {
-    Seeking,
-    D,
-    O,
-    N,
-    Apos,
-    T,
-    DoParen,
-    DontParen,
+impl Default for Machine {
+    fn default() -> Self {
+        Machine {
+            total: 0,
+            enabled: true,
+        }
+    }
 }
 
-pub fn solve(raw_data: &str, use_conditionals: bool) -> i32 {
-    let mut total = 0;
-    let mut mul_state = MulState::Seeking;
-    let mut cond_state = ConditionalState::Seeking;
-    let mut mul_allowed = true;
-
-    for char in raw_data.chars() {
-        if use_conditionals {
-            match cond_state {
-                ConditionalState::Seeking => if char == 'd' { cond_state = ConditionalState::D },
-                ConditionalState::D => match char {
-                    'o' => cond_state = ConditionalState::O,
-                    _ => cond_state = ConditionalState::Seeking,
-                },
-                ConditionalState::O => match char {
-                    'n' => cond_state = ConditionalState::N,
-                    '(' => cond_state = ConditionalState::DoParen,
-                    _ => cond_state = ConditionalState::Seeking,
-                },
-                ConditionalState::N => match char {
-                    '\'' => cond_state = ConditionalState::Apos,
-                    _ => cond_state = ConditionalState::Seeking,
-                },
-                ConditionalState::Apos => match char {
-                    't' => cond_state = ConditionalState::T,
-                    _ => cond_state = ConditionalState::Seeking,
-                },
-                ConditionalState::T => match char {
-                    '(' => cond_state = ConditionalState::DontParen,
-                    _ => cond_state = ConditionalState::Seeking,
-                },
-                ConditionalState::DoParen => match char {
-                    ')' => {
-                        mul_allowed = true;
-                        cond_state = ConditionalState::Seeking;
-                    },
-                    _ => {
-                        cond_state = ConditionalState::Seeking;
-                    }
-                },
-                ConditionalState::DontParen => match char {
-                    ')' => {
-                        mul_allowed = false;
-                        cond_state = ConditionalState::Seeking;
-                    },
-                    _ => {
-                        cond_state = ConditionalState::Seeking;
-                    }
-                } 
+// Operands in the puzzle are always 1-3 digit numbers
+const MAX_OPERAND_DIGITS: usize = 3;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+type Handler = fn(&mut Machine, &[i64]);
+
+struct InstructionDef {
+    name: &'static str,
+    arity: usize,
+    handler: Handler,
+}
+
+/// An instruction recognised in the corrupted memory, along with the byte span it came from.
+#[derive(Debug, PartialEq)]
+pub struct Token {
+    pub name: &'static str,
+    pub args: Vec<i64>,
+    pub span: Range<usize>,
+}
+
+enum Match<'s> {
+    Found(&'s InstructionDef, Vec<i64>, usize),
+    NotFound,
+    // Ran out of data partway through something that could still be an instruction
+    Incomplete,
+}
+
+/// What happened when a single token was executed.
+#[derive(Debug, PartialEq)]
+pub struct TraceEntry {
+    pub token: Token,
+    pub enabled: bool,
+    pub contribution: i64,
+}
+
+/// The set of instructions the tokenizer knows about and how to execute them.
+///
+/// An instruction is written as its name followed by `arity` comma separated numbers in
+/// parentheses, e.g. `mul(2,4)` or `do()`. Anything that doesn't exactly match a registered
+/// instruction is treated as corruption and skipped.
+pub struct InstructionSet {
+    defs: Vec<InstructionDef>,
+}
+
+impl InstructionSet {
+    pub fn new() -> Self {
+        InstructionSet { defs: Vec::new() }
+    }
+
+    pub fn register(mut self, name: &'static str, arity: usize, handler: Handler) -> Self {
+        self.defs.push(InstructionDef {
+            name,
+            arity,
+            handler,
+        });
+        self
+    }
+
+    fn match_def<'s>(
+        def: &'s InstructionDef,
+        data: &[u8],
+        start: usize,
+        at_end: bool,
+    ) -> Match<'s> {
+        // Running out of data only means there's no match if there's no more data coming
+        let out_of_data = || {
+            if at_end {
+                Match::NotFound
+            } else {
+                Match::Incomplete
+            }
+        };
+        let mut pos = start;
+        for expected in def.name.bytes().chain(std::iter::once(b'(')) {
+            match data.get(pos) {
+                None => return out_of_data(),
+                Some(&byte) if byte != expected => return Match::NotFound,
+                Some(_) => pos += 1,
             }
         }
-        match mul_state {
-            MulState::Seeking => if char == 'm' { mul_state = MulState::M },
-            MulState::M => match char {
-                'm' => {}
-                'u' => {
-                    mul_state = MulState::U;
-                }
-                _ => {
-                    mul_state = MulState::Seeking;
+        let mut args = Vec::with_capacity(def.arity);
+        for i in 0..def.arity {
+            if i > 0 {
+                match data.get(pos) {
+                    None => return out_of_data(),
+                    Some(b',') => pos += 1,
+                    Some(_) => return Match::NotFound,
                 }
-            },
-            MulState::U => match char {
-                'l' => {
-                    mul_state = MulState::L;
+            }
+            let digits = data[pos..]
+                .iter()
+                .take(MAX_OPERAND_DIGITS + 1)
+                .take_while(|b| b.is_ascii_digit())
+                .count();
+            // The operand might carry on into data we haven't seen yet
+            if pos + digits == data.len() {
+                return out_of_data();
+            }
+            // An overly long operand is just more corruption
+            if digits > MAX_OPERAND_DIGITS {
+                return Match::NotFound;
+            }
+            // So is an empty one
+            if digits == 0 {
+                return Match::NotFound;
+            }
+            let num = data[pos..pos + digits]
+                .iter()
+                .fold(0, |acc, digit| acc * 10 + i64::from(digit - b'0'));
+            args.push(num);
+            pos += digits;
+        }
+        match data.get(pos) {
+            None => out_of_data(),
+            Some(b')') => Match::Found(def, args, pos + 1),
+            Some(_) => Match::NotFound,
+        }
+    }
+
+    fn match_at(&self, data: &[u8], pos: usize, at_end: bool) -> Match<'_> {
+        for def in &self.defs {
+            match Self::match_def(def, data, pos, at_end) {
+                Match::NotFound => continue,
+                // If an earlier instruction might still match we have to wait and see before
+                // trying later ones, so the result doesn't depend on where chunks are split
+                result => return result,
+            }
+        }
+        Match::NotFound
+    }
+
+    // Scans `data` for instructions, passing each one found to `on_token`. `offset` is where
+    // `data` starts in the whole input, and `at_end` says whether any more data will follow it.
+    // Returns how many bytes were fully scanned; anything after that is the start of a possible
+    // instruction that needs more data to finish.
+    fn scan(
+        &self,
+        data: &[u8],
+        offset: usize,
+        at_end: bool,
+        on_token: &mut impl FnMut(Token),
+    ) -> usize {
+        let mut pos = 0;
+        while pos < data.len() {
+            match self.match_at(data, pos, at_end) {
+                Match::Found(def, args, end) => {
+                    on_token(Token {
+                        name: def.name,
+                        args,
+                        span: (offset + pos)..(offset + end),
+                    });
+                    pos = end;
                 }
-                _ => {
-                    mul_state = MulState::Seeking;
+                Match::NotFound => pos += 1,
+                Match::Incomplete => break,
+            }
+        }
+        pos
+    }
+
+    pub fn tokenize(&self, data: &[u8]) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        self.scan(data, 0, true, &mut |token| tokens.push(token));
+        tokens
+    }
+
+    /// Scans `reader` for instructions `chunk_size` bytes at a time. Only the current chunk and
+    /// the unfinished instruction at the end of the last one are ever held in memory.
+    pub fn scan_reader<R: Read>(
+        &self,
+        mut reader: R,
+        chunk_size: usize,
+        mut on_token: impl FnMut(Token),
+    ) -> io::Result<()> {
+        let mut chunk = vec![0; chunk_size];
+        let mut buffer = Vec::with_capacity(chunk_size * 2);
+        let mut offset = 0;
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let at_end = read == 0;
+            buffer.extend_from_slice(&chunk[..read]);
+            let scanned = self.scan(&buffer, offset, at_end, &mut on_token);
+            buffer.drain(..scanned);
+            offset += scanned;
+            if at_end {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn execute(&self, machine: &mut Machine, token: &Token) {
+        let def = self
+            .defs
+            .iter()
+            .find(|def| def.name == token.name)
+            .expect("tokens to only come from registered instructions");
+        (def.handler)(machine, &token.args);
+    }
+
+    pub fn run_reader<R: Read>(&self, reader: R, chunk_size: usize) -> io::Result<Machine> {
+        let mut machine = Machine::default();
+        self.scan_reader(reader, chunk_size, |token| {
+            self.execute(&mut machine, &token)
+        })?;
+        Ok(machine)
+    }
+
+    /// Runs the tokens like [`InstructionSet::run_reader`], but records whether the machine was
+    /// enabled before each one and how much it added to the total.
+    pub fn trace(&self, tokens: Vec<Token>) -> Vec<TraceEntry> {
+        let mut machine = Machine::default();
+        tokens
+            .into_iter()
+            .map(|token| {
+                let enabled = machine.enabled;
+                let total_before = machine.total;
+                self.execute(&mut machine, &token);
+                TraceEntry {
+                    token,
+                    enabled,
+                    contribution: machine.total - total_before,
                 }
+            })
+            .collect()
+    }
+}
+
+fn mul(machine: &mut Machine, args: &[i64]) {
+    if machine.enabled {
+        machine.total += args[0] * args[1];
+    }
+}
+
+fn enable(machine: &mut Machine, _: &[i64]) {
+    machine.enabled = true;
+}
+
+fn disable(machine: &mut Machine, _: &[i64]) {
+    machine.enabled = false;
+}
+
+fn puzzle_instructions(use_conditionals: bool) -> InstructionSet {
+    let set = InstructionSet::new().register("mul", 2, mul);
+    if use_conditionals {
+        set.register("do", 0, enable).register("don't", 0, disable)
+    } else {
+        set
+    }
+}
+
+pub fn solve_reader<R: Read>(reader: R, use_conditionals: bool) -> io::Result<i64> {
+    let instructions = puzzle_instructions(use_conditionals);
+    Ok(instructions.run_reader(reader, CHUNK_SIZE)?.total)
+}
+
+pub fn trace(raw_data: &str, use_conditionals: bool) -> Vec<TraceEntry> {
+    let instructions = puzzle_instructions(use_conditionals);
+    instructions.trace(instructions.tokenize(raw_data.as_bytes()))
+}
+
+fn print_trace(raw_data: &str, trace: &[TraceEntry]) {
+    for entry in trace {
+        let args = entry
+            .token
+            .args
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!(
+            "{:>8}  {:<12} {:<12} {} {:+}",
+            entry.token.span.start,
+            &raw_data[entry.token.span.clone()],
+            format!("[{}]", args),
+            if entry.enabled {
+                "enabled "
+            } else {
+                "disabled"
             },
-            MulState::L => match char {
-                '(' => {
-                    mul_state = MulState::FirstNumber(Vec::new());
-                }
-                _ => {
-                    mul_state = MulState::Seeking;
-                }
+            entry.contribution
+        );
+    }
+}
+
+/// Renders the corrupted memory with every recognised instruction coloured in: green for
+/// instructions that counted towards the total, red for ones with operands that were suppressed,
+/// and yellow for everything else (like `do()` and `don't()`).
+pub fn highlight(raw_data: &str, trace: &[TraceEntry]) -> String {
+    let mut out = String::with_capacity(raw_data.len());
+    let mut last_end = 0;
+    for entry in trace {
+        let span = entry.token.span.clone();
+        let colour = if entry.contribution != 0 {
+            ANSI_GREEN
+        } else if !entry.enabled && !entry.token.args.is_empty() {
+            ANSI_RED
+        } else {
+            ANSI_YELLOW
+        };
+        out.push_str(&raw_data[last_end..span.start]);
+        out.push_str(colour);
+        out.push_str(&raw_data[span.clone()]);
+        out.push_str(ANSI_RESET);
+        last_end = span.end;
+    }
+    out.push_str(&raw_data[last_end..]);
+    out
+}
+
+const INPUT_PATH: &str = "input/day3input.txt";
+
+fn open_input() -> File {
+    File::open(INPUT_PATH).expect("Failed to read input file!")
+}
+
+fn bench_scanner() {
+    const RUNS: u32 = 100;
+    let input_size = open_input()
+        .metadata()
+        .expect("input file to have metadata")
+        .len();
+    let per_run = bench(RUNS, || {
+        solve_reader(open_input(), true).expect("Failed to read input file!")
+    });
+    let throughput = input_size as f64 / (1024.0 * 1024.0) / per_run.as_secs_f64();
+    println!(
+        "Streaming scan took {:?} per run over {} bytes ({:.1} MiB/s)",
+        per_run, input_size, throughput
+    );
+}
+
+pub fn solution() {
+    // Stream the input rather than loading it all, so huge memory dumps don't need to fit in RAM
+    let without_conditionals =
+        solve_reader(open_input(), false).expect("Failed to read input file!");
+    println!("Solution without conditionals is {}", without_conditionals);
+    let with_conditionals = solve_reader(open_input(), true).expect("Failed to read input file!");
+    println!("Solution with conditionals is {}", with_conditionals);
+    if has_flag("--trace") || has_flag("--highlight") {
+        let raw_data = fs::read_to_string(INPUT_PATH).expect("Failed to read input file!");
+        let trace = trace(&raw_data, true);
+        if has_flag("--trace") {
+            print_trace(&raw_data, &trace);
+        }
+        if has_flag("--highlight") {
+            println!("{}", highlight(&raw_data, &trace));
+        }
+    }
+    if has_flag("--bench") {
+        bench_scanner();
+    }
+}
+
+#[test]
+fn it_works() {
+    let data1 = "asdhshsum(123,281)";
+    solve_reader(data1.as_bytes(), false).unwrap();
+}
+
+#[test]
+fn example_data() {
+    let data1 = "xmul(2,4)%&mul[3,7]!@^do_not_mul(5,5)+mul(32,64]then(mul(11,8)mul(8,5))";
+    assert_eq!(solve_reader(data1.as_bytes(), false).unwrap(), 161);
+    let data2 = "xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))";
+    assert_eq!(solve_reader(data2.as_bytes(), true).unwrap(), 48);
+}
+
+#[test]
+fn tokens_have_spans() {
+    let data = "xmul(2,4)don't()mul(,5)do()";
+    let tokens = puzzle_instructions(true).tokenize(data.as_bytes());
+    assert_eq!(
+        tokens,
+        vec![
+            Token {
+                name: "mul",
+                args: vec![2, 4],
+                span: 1..9,
             },
-            MulState::FirstNumber(ref mut acc) => match char {
-                '0'..='9' => {
-                    acc.push(char);
-                }
-                ',' => {
-                    mul_state = MulState::SecondNumber(acc.to_vec(), Vec::new());
-                }
-                _ => {
-                    mul_state = MulState::Seeking;
-                }
+            Token {
+                name: "don't",
+                args: vec![],
+                span: 9..16,
             },
-            MulState::SecondNumber(ref num1acc, ref mut num2acc) => match char {
-                '0'..='9' => {
-                    num2acc.push(char);
-                }
-                ')' => {
-                    if mul_allowed {
-                        let num1: i32 = num1acc
-                            .iter()
-                            .collect::<String>()
-                            .parse()
-                            .expect("Failed to parse string for num1!");
-                        let num2: i32 = num2acc
-                            .iter()
-                            .collect::<String>()
-                            .parse()
-                            .expect("Failed to parse string for num2!");
-                        total += num1 * num2;
-                        mul_state = MulState::Seeking;
-                    }
-                }
-                _ => {
-                    mul_state = MulState::Seeking;
-                }
+            Token {
+                name: "do",
+                args: vec![],
+                span: 23..27,
             },
+        ]
+    );
+}
+
+#[test]
+fn trace_records_suppressed_muls() {
+    let data = "mul(2,3)don't()mul(4,5)do()mul(1,7)";
+    let trace = trace(data, true);
+    let summary: Vec<_> = trace
+        .iter()
+        .map(|entry| (entry.token.span.start, entry.enabled, entry.contribution))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (0, true, 6),
+            (8, true, 0),
+            (15, false, 0),
+            (23, false, 0),
+            (27, true, 7)
+        ]
+    );
+    let highlighted = highlight(data, &trace);
+    assert!(highlighted.starts_with("\x1b[32mmul(2,3)\x1b[0m\x1b[33mdon't()"));
+    assert!(highlighted.contains("\x1b[31mmul(4,5)\x1b[0m"));
+}
+
+#[test]
+fn rejects_bad_operands() {
+    assert_eq!(
+        solve_reader(
+            "mul(,5)mul(5,)mul()mul(1234,2)mul(2,1234)".as_bytes(),
+            false
+        )
+        .unwrap(),
+        0
+    );
+    assert_eq!(
+        solve_reader("mul(999,999)mul(0001,2)mul(1,2,3)".as_bytes(), false).unwrap(),
+        998001
+    );
+}
+
+// Straightforward reimplementation of the puzzle rules to check the tokenizer against
+#[cfg(test)]
+fn reference_solve(data: &str, use_conditionals: bool) -> i64 {
+    let mut total = 0;
+    let mut enabled = true;
+    for i in 0..data.len() {
+        let rest = &data.as_bytes()[i..];
+        if use_conditionals && rest.starts_with(b"do()") {
+            enabled = true;
+        } else if use_conditionals && rest.starts_with(b"don't()") {
+            enabled = false;
+        } else if enabled && rest.starts_with(b"mul(") {
+            let Some(close) = rest.iter().position(|&b| b == b')') else {
+                continue;
+            };
+            let Ok(inner) = std::str::from_utf8(&rest[4..close]) else {
+                continue;
+            };
+            let valid = |operand: &str| {
+                (1..=3).contains(&operand.len()) && operand.bytes().all(|b| b.is_ascii_digit())
+            };
+            if let Some((left, right)) = inner.split_once(',') {
+                if valid(left) && valid(right) {
+                    total += left.parse::<i64>().unwrap() * right.parse::<i64>().unwrap();
+                }
+            }
         }
     }
     total
 }
 
-pub fn solution() {
-    let raw_data = fs::read_to_string("input/day3input.txt").expect("Failed to read input file!");
-    println!("Solution without conditionals is {}", solve(&raw_data, false));
-    println!("Solution with conditionals is {}", solve(&raw_data, true));
+#[cfg(test)]
+fn corrupted_memory() -> impl proptest::strategy::Strategy<Value = String> {
+    use proptest::{prop_oneof, strategy::Just, strategy::Strategy};
+    // Glue together pieces that look a lot like instructions so the tokenizer sees plenty of
+    // near misses
+    let fragment = prop_oneof![
+        Just("mul(".to_owned()),
+        Just("do(".to_owned()),
+        Just("don't(".to_owned()),
+        Just("do()".to_owned()),
+        Just("don't()".to_owned()),
+        "mul\\([0-9]{0,4},[0-9]{0,4}\\)",
+        "[0-9]{0,5}",
+        "[,()']",
+        "[a-z ]{0,3}",
+        "\\PC{0,2}",
+    ];
+    proptest::collection::vec(fragment, 0..40).prop_map(|fragments| fragments.concat())
 }
 
-#[test]
-fn it_works() {
-    let data1 = "asdhshsum(123,281)";
-    solve(data1, false);
+proptest! {
+    #[test]
+    fn matches_reference(data in corrupted_memory()) {
+        assert_eq!(solve_reader(data.as_bytes(), false).unwrap(), reference_solve(&data, false));
+        assert_eq!(solve_reader(data.as_bytes(), true).unwrap(), reference_solve(&data, true));
+    }
+
+    #[test]
+    fn chunk_boundaries_dont_matter(data in corrupted_memory(), chunk_size in 1..16_usize) {
+        for use_conditionals in [false, true] {
+            let instructions = puzzle_instructions(use_conditionals);
+            let mut streamed = Vec::new();
+            instructions
+                .scan_reader(data.as_bytes(), chunk_size, |token| streamed.push(token))
+                .expect("reading from a slice to never fail");
+            assert_eq!(streamed, instructions.tokenize(data.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn operands_are_in_range(data in corrupted_memory()) {
+        for token in puzzle_instructions(true).tokenize(data.as_bytes()) {
+            assert!(token.args.iter().all(|arg| (0..=999).contains(arg)));
+        }
+    }
 }
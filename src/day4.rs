@@ -1,7 +1,105 @@
+use crate::util::{flag_value, has_flag, ANSI_DIM, ANSI_GREEN, ANSI_RESET};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 
 struct WordSearch {
     vec: Vec<Vec<char>>,
+    // Length of the longest line, which is what x wraps around in wrap mode
+    width: usize,
+}
+
+/// A single place a word was found in the word search. For snake searches `direction` is the
+/// direction of the first step, and `path` has every cell the word went through. One-letter
+/// words have no steps, so their `direction` is `(0, 0)`.
+#[derive(Debug)]
+struct WordMatch<'w> {
+    word: &'w str,
+    start: (usize, usize),
+    direction: (i32, i32),
+    end: (usize, usize),
+    path: Vec<(usize, usize)>,
+}
+
+/// Controls how words can be laid out in the word search.
+#[derive(Clone)]
+struct SearchOptions {
+    // Steps that can be taken from one letter of a word to the next
+    directions: Vec<(i32, i32)>,
+    // Walking off one edge of the grid comes back in on the opposite edge
+    wrap: bool,
+    // Words can bend, taking a different step between each letter (but never reusing a cell)
+    snake: bool,
+}
+
+static KNIGHT_MOVES: [(i32, i32); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+
+impl Default for SearchOptions {
+    // Straight lines in any of the eight directions, like the puzzle
+    fn default() -> Self {
+        SearchOptions {
+            directions: DIRECTIONS.to_vec(),
+            wrap: false,
+            snake: false,
+        }
+    }
+}
+
+// State for one depth first walk through the grid and trie at the same time
+struct Walk<'a, 'w> {
+    trie: &'a Trie,
+    words: &'a [&'w str],
+    options: &'a SearchOptions,
+    first_direction: (i32, i32),
+    path: Vec<(usize, usize)>,
+    matches: &'a mut Vec<WordMatch<'w>>,
+}
+
+#[derive(Default)]
+struct TrieNode {
+    children: HashMap<char, usize>,
+    // Index of the word that ends at this node, if any
+    word: Option<usize>,
+}
+
+// Prefix tree over all the words being searched for, so every word can be checked in a single
+// walk from each cell rather than once per word
+struct Trie {
+    nodes: Vec<TrieNode>,
+}
+
+impl Trie {
+    fn new(words: &[&str]) -> Self {
+        let mut nodes = vec![TrieNode::default()];
+        for (word_idx, word) in words.iter().enumerate() {
+            let mut node = 0;
+            for char in word.chars() {
+                node = match nodes[node].children.get(&char) {
+                    Some(&next) => next,
+                    None => {
+                        nodes.push(TrieNode::default());
+                        let next = nodes.len() - 1;
+                        nodes[node].children.insert(char, next);
+                        next
+                    }
+                };
+            }
+            nodes[node].word.get_or_insert(word_idx);
+        }
+        Trie { nodes }
+    }
+
+    fn child(&self, node: usize, char: &char) -> Option<usize> {
+        self.nodes[node].children.get(char).copied()
+    }
 }
 
 static DIRECTIONS: [(i32, i32); 8] = [
@@ -42,136 +140,557 @@ impl WordSearch {
             Some(i) => i == char,
         }
     }
-    fn search_word(
+    fn step(
         &self,
-        startx: usize,
-        starty: usize,
-        word: &str,
-        dir_filter: Vec<(i32, i32)>,
-    ) -> Vec<(i32, i32)> {
-        // returns number of directions that matched
-        DIRECTIONS
-            .iter()
-            .filter(|dir| dir_filter.is_empty() || dir_filter.contains(dir))
-            .filter_map(|(offx, offy)| {
-                let mut curx = startx;
-                let mut cury = starty;
-                for (i, search_char) in word.chars().enumerate() {
-                    if !self.is_at(curx, cury, &search_char) {
-                        return None;
+        x: usize,
+        y: usize,
+        (offx, offy): (i32, i32),
+        wrap: bool,
+    ) -> Option<(usize, usize)> {
+        if wrap {
+            // Treat the grid as a torus as wide as its longest line, so straight moves stay in
+            // line. Cells past the end of a shorter line are missing, just like off the grid
+            let height = i32::try_from(self.vec.len()).ok().filter(|&h| h > 0)?;
+            let width = i32::try_from(self.width).ok().filter(|&w| w > 0)?;
+            let newx = usize::try_from((i32::try_from(x).ok()? + offx).rem_euclid(width)).ok()?;
+            let newy = usize::try_from((i32::try_from(y).ok()? + offy).rem_euclid(height)).ok()?;
+            self.get_at(newx, newy)?;
+            return Some((newx, newy));
+        }
+        let newx: usize = (i32::try_from(x).ok()? + offx).try_into().ok()?;
+        let newy: usize = (i32::try_from(y).ok()? + offy).try_into().ok()?;
+        Some((newx, newy))
+    }
+
+    // Follows the trie from `node` into the cell at `pos`, recording any words that end there and
+    // then carrying on to the next cells
+    fn walk<'w>(
+        &self,
+        walk: &mut Walk<'_, 'w>,
+        node: usize,
+        pos: (usize, usize),
+        direction: (i32, i32),
+    ) {
+        let Some(node) = self
+            .get_at(pos.0, pos.1)
+            .and_then(|char| walk.trie.child(node, char))
+        else {
+            return;
+        };
+        if walk.options.snake && walk.path.contains(&pos) {
+            return;
+        }
+        walk.path.push(pos);
+        if let Some(word_idx) = walk.trie.nodes[node].word {
+            walk.matches.push(WordMatch {
+                word: walk.words[word_idx],
+                start: walk.path[0],
+                direction: walk.first_direction,
+                end: pos,
+                path: walk.path.clone(),
+            });
+        }
+        // The first step always goes in the starting direction, so each snake is only found
+        // once per starting direction
+        let options = walk.options;
+        let next_directions = if options.snake && walk.path.len() > 1 {
+            options.directions.as_slice()
+        } else {
+            std::slice::from_ref(&direction)
+        };
+        for &next_direction in next_directions {
+            if let Some(next) = self.step(pos.0, pos.1, next_direction, options.wrap) {
+                self.walk(walk, node, next, next_direction);
+            }
+        }
+        walk.path.pop();
+    }
+
+    /// Finds every occurrence of every word in `words`, in any of the eight directions.
+    fn find_words<'w>(&self, words: &[&'w str]) -> Vec<WordMatch<'w>> {
+        self.find_words_with(words, &SearchOptions::default())
+    }
+
+    /// Finds every occurrence of every word in `words`, laid out however `options` allows.
+    fn find_words_with<'w>(
+        &self,
+        words: &[&'w str],
+        options: &SearchOptions,
+    ) -> Vec<WordMatch<'w>> {
+        let trie = Trie::new(words);
+        let mut matches = Vec::new();
+        for y in 0..self.vec.len() {
+            for (x, char) in self.vec[y].iter().enumerate() {
+                let Some(root) = trie.child(0, char) else {
+                    continue;
+                };
+                // A one-letter word reads the same in every direction, so it's only found once
+                if let Some(word_idx) = trie.nodes[root].word {
+                    matches.push(WordMatch {
+                        word: words[word_idx],
+                        start: (x, y),
+                        direction: (0, 0),
+                        end: (x, y),
+                        path: vec![(x, y)],
+                    });
+                }
+                for &direction in options.directions.iter() {
+                    let Some(next) = self.step(x, y, direction, options.wrap) else {
+                        continue;
                     };
-                    if i < word.len() - 1 {
-                        let newx: usize = (i32::try_from(curx).ok()? + offx).try_into().ok()?;
-                        let newy: usize = (i32::try_from(cury).ok()? + offy).try_into().ok()?;
-                        curx = newx;
-                        cury = newy;
-                    }
+                    let mut walk = Walk {
+                        trie: &trie,
+                        words,
+                        options,
+                        first_direction: direction,
+                        path: vec![(x, y)],
+                        matches: &mut matches,
+                    };
+                    self.walk(&mut walk, root, next, direction);
                 }
-                Some((*offx, *offy))
-            })
+            }
+        }
+        matches
+    }
+}
+
+/// A small grid of characters to look for in a word search. `.` matches any character.
+#[derive(Clone, PartialEq)]
+struct Pattern {
+    cells: Vec<Vec<Option<char>>>,
+}
+
+static X_MAS: &str = "M.S
+.A.
+M.S";
+
+impl Pattern {
+    fn width(&self) -> usize {
+        self.cells.first().map_or(0, |row| row.len())
+    }
+
+    // Rotates a quarter turn clockwise
+    fn rotate(&self) -> Self {
+        let height = self.cells.len();
+        Pattern {
+            cells: (0..self.width())
+                .map(|x| (0..height).rev().map(|y| self.cells[y][x]).collect())
+                .collect(),
+        }
+    }
+
+    // Mirrors left to right
+    fn reflect(&self) -> Self {
+        Pattern {
+            cells: self
+                .cells
+                .iter()
+                .map(|row| row.iter().rev().copied().collect())
+                .collect(),
+        }
+    }
+
+    // Every distinct rotation and reflection of this pattern, as its anchored cells. Symmetrical
+    // patterns have fewer than eight, which stops the same match from being counted more than
+    // once. Comparing anchored cells rather than whole patterns means wildcards around the edges
+    // can't make two variants look different when they match the same cells
+    fn variants(&self) -> Vec<Vec<(usize, usize, char)>> {
+        let mut variants = Vec::new();
+        for start in [self.clone(), self.reflect()] {
+            let mut variant = start;
+            for _ in 0..4 {
+                let cells = variant.anchored_cells();
+                if !variants.contains(&cells) {
+                    variants.push(cells);
+                }
+                variant = variant.rotate();
+            }
+        }
+        variants
+    }
+
+    // The non-wildcard cells, shifted up and left until they touch the top and left edges
+    fn anchored_cells(&self) -> Vec<(usize, usize, char)> {
+        let cells: Vec<_> = self.fixed_cells().collect();
+        let min_x = cells.iter().map(|&(x, _, _)| x).min().unwrap_or(0);
+        let min_y = cells.iter().map(|&(_, y, _)| y).min().unwrap_or(0);
+        cells
+            .into_iter()
+            .map(|(x, y, char)| (x - min_x, y - min_y, char))
             .collect()
     }
+
+    // Positions of every non-wildcard cell, relative to the top left corner
+    fn fixed_cells(&self) -> impl Iterator<Item = (usize, usize, char)> + '_ {
+        self.cells.iter().enumerate().flat_map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .filter_map(move |(x, cell)| cell.map(|char| (x, y, char)))
+        })
+    }
 }
 
-impl From<&str> for WordSearch {
+impl From<&str> for Pattern {
     fn from(value: &str) -> Self {
-        WordSearch {
-            vec: value.lines().map(|line| line.chars().collect()).collect(),
-        }
+        let mut cells: Vec<Vec<Option<char>>> = value
+            .lines()
+            .map(|line| {
+                line.chars()
+                    .map(|char| match char {
+                        '.' => None,
+                        c => Some(c),
+                    })
+                    .collect()
+            })
+            .collect();
+        // Pad out short lines with wildcards so the pattern is always rectangular
+        let width = cells.iter().map(|row| row.len()).max().unwrap_or(0);
+        cells.iter_mut().for_each(|row| row.resize(width, None));
+        Pattern { cells }
     }
 }
 
-fn count_xmas(data: &str) {
-    let wordsearch = WordSearch::from(data);
-    let mut count = 0;
-    for y in 0..wordsearch.vec.len() {
-        // possible optimization: all lines are same length in input data
-        for x in 0..(wordsearch.vec[y].len()) {
-            if *wordsearch
-                .get_at(x, y)
-                .expect("solve to not iterate outside of arrays")
-                != 'X'
-            {
-                continue;
-            };
-            count += wordsearch.search_word(x, y, "XMAS", Vec::new()).len();
+/// A place a pattern was found, with the word search cells that its non-wildcard cells covered.
+#[derive(Debug)]
+struct PatternMatch {
+    cells: Vec<(usize, usize)>,
+}
+
+impl WordSearch {
+    /// Finds every place `pattern` appears under any rotation or reflection.
+    fn find_pattern(&self, pattern: &Pattern) -> Vec<PatternMatch> {
+        let variants = pattern.variants();
+        let mut matches = Vec::new();
+        for y in 0..self.vec.len() {
+            for x in 0..self.vec[y].len() {
+                for variant in &variants {
+                    let matched = variant
+                        .iter()
+                        .all(|&(offx, offy, char)| self.is_at(x + offx, y + offy, &char));
+                    if matched {
+                        matches.push(PatternMatch {
+                            cells: variant
+                                .iter()
+                                .map(|&(offx, offy, _)| (x + offx, y + offy))
+                                .collect(),
+                        });
+                    }
+                }
+            }
         }
+        matches
     }
-    println!("XMAS count is {}", count);
 }
 
-fn count_ecks_mas(data: &str) {
-    let wordsearch = WordSearch::from(data);
-    let mut count = 0;
-    for y in 0..wordsearch.vec.len() {
-        // possible optimization: all lines are same length in input data
-        for x in 0..(wordsearch.vec[y].len()) {
-            if *wordsearch
-                .get_at(x, y)
-                .expect("solve to not iterate outside of arrays")
-                != 'M'
-            {
-                continue;
-            };
-            let directions = wordsearch.search_word(
-                x,
-                y,
-                "MAS",
-                vec![
-                    // Down-right (with a matching up-right)
-                    (1, 1),
-                    // Up-right (with a matching up-left)
-                    (1, -1),
-                    // Up-left (with a matching down-left)
-                    (-1, -1),
-                    // Down-left (with a matching down-right)
-                    (-1, 1),
-                ],
-            );
-            for dir in directions {
-                let offset = match dir {
-                    (1, 1) => (0, 2),
-                    (1, -1) => (2, 0),
-                    (-1, -1) => (0, -2),
-                    (-1, 1) => (-2, 0),
-                    _ => panic!("Unexpected direction"),
-                };
-                let other_m_x = match usize::try_from(
-                    i32::try_from(x).expect("any x to convert to i32") + offset.0,
-                ) {
-                    Ok(i) => i,
-                    Err(_) => continue,
-                };
-                let other_m_y = match usize::try_from(
-                    i32::try_from(y).expect("any x to convert to i32") + offset.1,
-                ) {
-                    Ok(i) => i,
-                    Err(_) => continue,
-                };
-                if let Some('M') = wordsearch.get_at(other_m_x, other_m_y) {
-                    let dirs_to_check = match dir {
-                        (1, 1) => vec![(1, -1)],
-                        (1, -1) => vec![(-1, -1)],
-                        (-1, -1) => vec![(-1, 1)],
-                        (-1, 1) => vec![(1, 1)],
-                        _ => panic!("Unexpected direction"),
-                    };
-                    let other_m_directions =
-                        wordsearch.search_word(other_m_x, other_m_y, "MAS", dirs_to_check);
-                    match other_m_directions.len() {
-                        0 => {}
-                        1 => count += 1,
-                        2.. => panic!("Somehow found more than one direction"),
+#[derive(Clone, Copy)]
+enum HighlightStyle {
+    Plain,
+    Ansi,
+}
+
+impl WordSearch {
+    /// Renders the word search with only the given cells showing. In plain style every other
+    /// cell is replaced with `.`; in ANSI style the shown cells are also coloured in and the
+    /// `.`s are dimmed.
+    fn render_highlighted(&self, cells: &HashSet<(usize, usize)>, style: HighlightStyle) -> String {
+        let mut out = String::new();
+        for (y, line) in self.vec.iter().enumerate() {
+            for (x, char) in line.iter().enumerate() {
+                let shown = cells.contains(&(x, y));
+                match (style, shown) {
+                    (HighlightStyle::Plain, true) => out.push(*char),
+                    (HighlightStyle::Plain, false) => out.push('.'),
+                    (HighlightStyle::Ansi, true) => {
+                        out.push_str(ANSI_GREEN);
+                        out.push(*char);
+                        out.push_str(ANSI_RESET);
+                    }
+                    (HighlightStyle::Ansi, false) => {
+                        out.push_str(ANSI_DIM);
+                        out.push('.');
+                        out.push_str(ANSI_RESET);
                     }
                 }
             }
+            out.push('\n');
         }
+        out
+    }
+}
+
+impl From<&str> for WordSearch {
+    fn from(value: &str) -> Self {
+        let vec: Vec<Vec<char>> = value.lines().map(|line| line.chars().collect()).collect();
+        let width = vec.iter().map(Vec::len).max().unwrap_or(0);
+        WordSearch { vec, width }
     }
+}
+
+fn count_xmas(data: &str) {
+    let wordsearch = WordSearch::from(data);
+    let count = wordsearch.find_words(&["XMAS"]).len();
+    println!("XMAS count is {}", count);
+}
+
+fn count_ecks_mas(data: &str) {
+    let wordsearch = WordSearch::from(data);
+    let count = wordsearch.find_pattern(&Pattern::from(X_MAS)).len();
     println!("X-MAS count is {}", count);
 }
 
+fn parse_step(step: &str) -> (i32, i32) {
+    let (x, y) = step
+        .split_once(',')
+        .expect("steps to be written as x,y pairs");
+    (
+        x.trim().parse().expect("step x to be a number"),
+        y.trim().parse().expect("step y to be a number"),
+    )
+}
+
 pub fn solution() {
     let raw_data = fs::read_to_string("input/day4input.txt").expect("Failed to read input file!");
     count_xmas(&raw_data);
     count_ecks_mas(&raw_data);
+    // Pass `--highlight` to show which letters are part of a match, plus `--color` to use
+    // ANSI colours
+    if has_flag("--highlight") {
+        let style = if has_flag("--color") {
+            HighlightStyle::Ansi
+        } else {
+            HighlightStyle::Plain
+        };
+        let wordsearch = WordSearch::from(raw_data.as_str());
+        let xmas_cells = wordsearch
+            .find_words(&["XMAS"])
+            .into_iter()
+            .flat_map(|found| found.path)
+            .collect();
+        println!("{}", wordsearch.render_highlighted(&xmas_cells, style));
+        let x_mas_cells = wordsearch
+            .find_pattern(&Pattern::from(X_MAS))
+            .into_iter()
+            .flat_map(|found| found.cells)
+            .collect();
+        println!("{}", wordsearch.render_highlighted(&x_mas_cells, style));
+    }
+    // Pass `--words A,B,C` to list every place the given words appear. The layout of the words
+    // can be changed with `--wrap`, `--snake`, `--knight` or `--steps 1,2/-2,0`
+    if let Some(words) = flag_value("--words") {
+        let words: Vec<&str> = words.split(',').collect();
+        let wordsearch = WordSearch::from(raw_data.as_str());
+        let mut options = SearchOptions {
+            wrap: has_flag("--wrap"),
+            snake: has_flag("--snake"),
+            ..Default::default()
+        };
+        if has_flag("--knight") {
+            options.directions = KNIGHT_MOVES.to_vec();
+        }
+        if let Some(steps) = flag_value("--steps") {
+            options.directions = steps.split('/').map(parse_step).collect();
+        }
+        for found in wordsearch.find_words_with(&words, &options) {
+            println!(
+                "{} from {:?} going {:?} to {:?} through {:?}",
+                found.word, found.start, found.direction, found.end, found.path
+            );
+        }
+    }
+    // Pass `--pattern M.S/.A./M.S` to list every place a pattern appears, with `/` separating
+    // the rows
+    if let Some(pattern) = flag_value("--pattern") {
+        let pattern = Pattern::from(pattern.replace('/', "\n").as_str());
+        let wordsearch = WordSearch::from(raw_data.as_str());
+        for found in wordsearch.find_pattern(&pattern) {
+            println!("Pattern covering {:?}", found.cells);
+        }
+    }
+}
+
+#[cfg(test)]
+const EXAMPLE: &str = "MMMSXXMASM
+MSAMXMSMSA
+AMXSXMAAMM
+MSAMASMSMX
+XMASAMXAMM
+XXAMMXXAMA
+SMSMSASXSS
+SAXAMASAAA
+MAMMMXMMMM
+MXMXAXMASX
+";
+
+#[test]
+fn finds_multiple_words() {
+    let wordsearch = WordSearch::from("CAT\nXAX\nTAC\n");
+    let mut matches: Vec<_> = wordsearch
+        .find_words(&["CAT", "CA", "AA"])
+        .into_iter()
+        .map(|m| (m.word, m.start, m.direction, m.end))
+        .collect();
+    matches.sort();
+    assert_eq!(
+        matches,
+        vec![
+            ("AA", (1, 0), (0, 1), (1, 1)),
+            ("AA", (1, 1), (0, -1), (1, 0)),
+            ("AA", (1, 1), (0, 1), (1, 2)),
+            ("AA", (1, 2), (0, -1), (1, 1)),
+            ("CA", (0, 0), (1, 0), (1, 0)),
+            ("CA", (0, 0), (1, 1), (1, 1)),
+            ("CA", (2, 2), (-1, -1), (1, 1)),
+            ("CA", (2, 2), (-1, 0), (1, 2)),
+            ("CAT", (0, 0), (1, 0), (2, 0)),
+            ("CAT", (2, 2), (-1, 0), (0, 2)),
+        ]
+    );
+}
+
+#[test]
+fn one_letter_words_match_once_per_cell() {
+    let wordsearch = WordSearch::from("XA\nAX\n");
+    let mut matches: Vec<_> = wordsearch
+        .find_words(&["X", "XA"])
+        .into_iter()
+        .map(|m| (m.word, m.start, m.direction))
+        .collect();
+    matches.sort();
+    assert_eq!(
+        matches,
+        vec![
+            ("X", (0, 0), (0, 0)),
+            ("X", (1, 1), (0, 0)),
+            ("XA", (0, 0), (0, 1)),
+            ("XA", (0, 0), (1, 0)),
+            ("XA", (1, 1), (-1, 0)),
+            ("XA", (1, 1), (0, -1)),
+        ]
+    );
+}
+
+#[test]
+fn example_xmas_count() {
+    let wordsearch = WordSearch::from(EXAMPLE);
+    assert_eq!(wordsearch.find_words(&["XMAS"]).len(), 18);
+}
+
+#[test]
+fn example_x_mas_count() {
+    let wordsearch = WordSearch::from(EXAMPLE);
+    assert_eq!(wordsearch.find_pattern(&Pattern::from(X_MAS)).len(), 9);
+}
+
+#[test]
+fn symmetrical_patterns_match_once_per_position() {
+    let plus = Pattern::from(".A\nABA\n.A");
+    assert_eq!(plus.variants().len(), 1);
+    let wordsearch = WordSearch::from("XAXX\nABAX\nXABA\nXXAX\n");
+    let matches = wordsearch.find_pattern(&plus);
+    assert_eq!(matches.len(), 2);
+    assert_eq!(
+        matches[0].cells,
+        vec![(1, 0), (0, 1), (1, 1), (2, 1), (1, 2)]
+    );
+}
+
+#[test]
+fn wildcard_edges_match_once_per_position() {
+    let wordsearch = WordSearch::from("XA\nAB\n");
+    assert_eq!(Pattern::from("A.").variants().len(), 1);
+    assert_eq!(wordsearch.find_pattern(&Pattern::from("A.")).len(), 2);
+    // Only the wildcard border differs between these rotations
+    let pattern = Pattern::from("...\n.AB\n...");
+    assert_eq!(pattern.variants().len(), 4);
+    let mut matches: Vec<_> = wordsearch
+        .find_pattern(&pattern)
+        .into_iter()
+        .map(|found| found.cells)
+        .collect();
+    matches.sort();
+    assert_eq!(matches, vec![vec![(0, 1), (1, 1)], vec![(1, 0), (1, 1)]]);
+}
+
+#[test]
+fn highlights_example_matches() {
+    let wordsearch = WordSearch::from(
+        "..X...
+.SAMX.
+.A..A.
+XMAS.S
+.X....
+",
+    );
+    let cells = wordsearch
+        .find_words(&["XMAS"])
+        .into_iter()
+        .flat_map(|found| found.path)
+        .collect();
+    assert_eq!(
+        wordsearch.render_highlighted(&cells, HighlightStyle::Plain),
+        "..X...
+.SAMX.
+.A..A.
+XMAS.S
+.X....
+"
+    );
+    let wordsearch = WordSearch::from("M.S\nMAS\nMSS\n");
+    let cells = wordsearch
+        .find_pattern(&Pattern::from(X_MAS))
+        .into_iter()
+        .flat_map(|found| found.cells)
+        .collect();
+    assert_eq!(
+        wordsearch.render_highlighted(&cells, HighlightStyle::Plain),
+        "M.S\n.A.\nM.S\n"
+    );
+    assert!(wordsearch
+        .render_highlighted(&cells, HighlightStyle::Ansi)
+        .starts_with("\x1b[32mM\x1b[0m\x1b[2m.\x1b[0m"));
+}
+
+#[test]
+fn search_options() {
+    let wordsearch = WordSearch::from("ASXM\nXBCD\nEFGH\n");
+    let straight = wordsearch.find_words(&["XMAS"]);
+    assert!(straight.is_empty());
+    let wrapping = SearchOptions {
+        wrap: true,
+        ..Default::default()
+    };
+    let found = wordsearch.find_words_with(&["XMAS"], &wrapping);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].path, vec![(2, 0), (3, 0), (0, 0), (1, 0)]);
+
+    // Ragged grids wrap at the longest line, and moving into a shorter line doesn't change column
+    let wordsearch = WordSearch::from("ABC\nMASX\n\n");
+    let found = wordsearch.find_words_with(&["XMAS"], &wrapping);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].path, vec![(3, 1), (0, 1), (1, 1), (2, 1)]);
+    let wordsearch = WordSearch::from("ABCDEF\nXYZ\n");
+    assert!(wordsearch.find_words_with(&["FZ"], &wrapping).is_empty());
+    let wordsearch = WordSearch::from("\nXMAS\n");
+    assert_eq!(wordsearch.find_words_with(&["XMAS"], &wrapping).len(), 1);
+
+    let wordsearch = WordSearch::from("X....\n..M..\n....A\n");
+    let knight = SearchOptions {
+        directions: KNIGHT_MOVES.to_vec(),
+        ..Default::default()
+    };
+    let found = wordsearch.find_words_with(&["XMA"], &knight);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].direction, (2, 1));
+    assert_eq!(found[0].end, (4, 2));
+
+    let wordsearch = WordSearch::from("XMA\n..S\n");
+    assert!(wordsearch.find_words(&["XMAS"]).is_empty());
+    let snake = SearchOptions {
+        snake: true,
+        ..Default::default()
+    };
+    let found = wordsearch.find_words_with(&["XMAS", "XMX"], &snake);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].direction, (1, 0));
+    assert_eq!(found[0].path, vec![(0, 0), (1, 0), (2, 0), (2, 1)]);
 }
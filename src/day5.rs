@@ -1,115 +1,519 @@
+use crate::util::has_flag;
 use std::collections::{HashMap, HashSet};
-use std::fs;
+use std::str::FromStr;
+use std::{fmt, fs};
 
-pub fn set_is_valid(set: &[u8], rules_map: &HashMap<u8, HashSet<u8>>) -> bool {
+/// A page number from the safety manual updates.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PageId(pub u32);
+
+impl FromStr for PageId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        s.parse()
+            .map(PageId)
+            .map_err(|e| format!("invalid page number {:?}: {}", s, e))
+    }
+}
+
+impl fmt::Display for PageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A rule `before|after` that an update breaks by putting `after` ahead of `before`.
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub before: PageId,
+    pub after: PageId,
+    pub before_position: usize,
+    pub after_position: usize,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "breaks {}|{}: {} at position {} comes after {} at position {}",
+            self.before,
+            self.after,
+            self.before,
+            self.before_position,
+            self.after,
+            self.after_position
+        )
+    }
+}
+
+pub fn find_violations(
+    set: &[PageId],
+    rules_map: &HashMap<PageId, HashSet<PageId>>,
+) -> Vec<Violation> {
+    let mut violations = Vec::new();
     for (i, num) in set.iter().enumerate().skip(1) {
-        let previous = &set[..=i];
-        match rules_map.get(num) {
-            None => continue,
-            Some(not_allowed) => {
-                for prevnum in previous {
-                    if not_allowed.contains(prevnum) {
-                        return false;
-                    }
-                }
+        let Some(not_allowed) = rules_map.get(num) else {
+            continue;
+        };
+        for (j, prevnum) in set[..i].iter().enumerate() {
+            if not_allowed.contains(prevnum) {
+                violations.push(Violation {
+                    before: *num,
+                    after: *prevnum,
+                    before_position: i,
+                    after_position: j,
+                });
             }
         }
     }
-    true
-}
-
-pub fn fix_set(mut set: Vec<u8>, rules_map: &HashMap<u8, HashSet<u8>>) -> Vec<u8> {
-    // Iterate over every number in set
-    for i in 0..set.len() {
-        match rules_map.get(&set[i]) {
-            None => continue,
-            Some(not_allowed) => {
-                // Iterate over previous numbers
-                for j in 0..i {
-                    // If we run into any number that should be after set[i], swap them, then move
-                    // on to the next i
-                    if not_allowed.contains(&set[j]) {
-                        set[j..=i].rotate_right(1);
-                        break;
-                    }
-                }
+    violations
+}
+
+pub fn set_is_valid(set: &[PageId], rules_map: &HashMap<PageId, HashSet<PageId>>) -> bool {
+    find_violations(set, rules_map).is_empty()
+}
+
+/// Lists every rule an update breaks, followed by the update and its fixed order side by side,
+/// with a `*` next to each position that changed.
+pub fn explain_update(
+    set: &[PageId],
+    fixed: &Result<Vec<PageId>, OrderingError>,
+    rules_map: &HashMap<PageId, HashSet<PageId>>,
+) -> String {
+    let mut out = String::new();
+    for violation in find_violations(set, rules_map) {
+        out += &format!("  {}\n", violation);
+    }
+    match fixed {
+        Ok(fixed) => {
+            out += "  pos  original  fixed\n";
+            for (i, (original, fixed)) in set.iter().zip(fixed).enumerate() {
+                let marker = if original == fixed { "" } else { " *" };
+                out += &format!("  {:>3}  {:>8}  {:>5}{}\n", i, original, fixed, marker);
             }
         }
+        Err(e) => out += &format!("  can't be fixed: {}\n", e),
     }
-    set
+    out
 }
 
-pub fn solve(data: &str) {
-    let mut rules_map: HashMap<u8, HashSet<u8>> = HashMap::new();
-    let mut split: usize = 0;
-    for (i, ordline) in data.lines().enumerate() {
-        if ordline.is_empty() {
-            split = i;
-            break;
-        };
-        let (left, right) = ordline.split_at(2);
-        let before: u8 = left
-            .parse()
-            .expect("left side number to be parseable into u8");
-        let after: u8 = right[1..]
-            .parse()
-            .expect("right side number to be parseable into u8");
-        // Add `after` to the list of numbers that cannot be before `before`
-        let after_set = rules_map.entry(before).or_default();
-        after_set.insert(after);
-    }
-    let mut valid_sets: Vec<Vec<u8>> = Vec::new();
-    for updateline in data.lines().skip(split + 1) {
-        if updateline.is_empty() {
-            continue;
+/// Why an update couldn't be put into a single correct order.
+#[derive(Debug, PartialEq)]
+pub enum OrderingError {
+    /// The rules between these pages form a cycle, so no order satisfies all of them.
+    Cyclic(Vec<PageId>),
+    /// No rule decides which of these two pages goes first, so there's more than one valid order.
+    Ambiguous(PageId, PageId),
+    /// This page appears more than once in the update, so it can't have a single position.
+    DuplicatePage(PageId),
+}
+
+impl fmt::Display for OrderingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderingError::Cyclic(pages) => {
+                write!(f, "rules between pages {:?} form a cycle", pages)
+            }
+            OrderingError::Ambiguous(a, b) => {
+                write!(f, "no rule decides whether {} or {} comes first", a, b)
+            }
+            OrderingError::DuplicatePage(page) => {
+                write!(f, "page {} appears more than once", page)
+            }
         }
-        let updateset: Vec<u8> = updateline
-            .split(',')
-            .map(|strnum| {
-                strnum
-                    .parse::<u8>()
-                    .expect("all numbers in input data to be parseable")
-            })
-            .collect();
-        if set_is_valid(&updateset, &rules_map) {
-            valid_sets.push(updateset);
+    }
+}
+
+/// Sorts the pages of an update so they follow every rule that applies to them.
+///
+/// Only rules where both pages are in the update are considered. The pages are topologically
+/// sorted, and since the puzzle expects a single correct order, an error is returned if the
+/// rules allow more than one (or none at all), or if a page is repeated.
+pub fn order_update(
+    set: &[PageId],
+    rules_map: &HashMap<PageId, HashSet<PageId>>,
+) -> Result<Vec<PageId>, OrderingError> {
+    let mut seen = HashSet::new();
+    if let Some(&page) = set.iter().find(|&&page| !seen.insert(page)) {
+        return Err(OrderingError::DuplicatePage(page));
+    }
+    // Count how many pages in this update have to come before each page
+    let mut in_degree: HashMap<PageId, usize> = set.iter().map(|&page| (page, 0)).collect();
+    for page in set {
+        for after in rules_map.get(page).into_iter().flatten() {
+            if let Some(count) = in_degree.get_mut(after) {
+                *count += 1;
+            }
         }
     }
-    let answer: usize = valid_sets
-        .into_iter()
-        .map(|set| usize::from(set[set.len() / 2]))
-        .sum();
-    println!("Total of middle numbers of correct sets is {}", answer);
-    let mut fixed_sets: Vec<Vec<u8>> = Vec::new();
-    for updateline in data.lines().skip(split + 1) {
-        if updateline.is_empty() {
-            continue;
+    // Go through pages in their original order so results are deterministic
+    let mut ready: Vec<PageId> = set
+        .iter()
+        .copied()
+        .filter(|page| in_degree[page] == 0)
+        .collect();
+    let mut ordered = Vec::with_capacity(set.len());
+    let mut ambiguous = None;
+    while let Some(&page) = ready.first() {
+        // If more than one page is free to go next, either could be placed first. Keep going
+        // anyway, since a cycle later on is the bigger problem
+        if let Some(&other) = ready.get(1) {
+            ambiguous.get_or_insert((page, other));
         }
-        let mut updateset: Vec<u8> = updateline
-            .split(',')
-            .map(|strnum| {
-                strnum
-                    .parse::<u8>()
-                    .expect("all numbers in input data to be parseable")
-            })
-            .collect();
-        if !set_is_valid(&updateset, &rules_map) {
-            updateset = fix_set(updateset, &rules_map);
-            assert!(set_is_valid(&updateset, &rules_map));
-            fixed_sets.push(updateset);
+        ready.remove(0);
+        ordered.push(page);
+        for after in rules_map.get(&page).into_iter().flatten() {
+            if let Some(count) = in_degree.get_mut(after) {
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(*after);
+                }
+            }
         }
     }
-    let answer_two: usize = fixed_sets
+    if ordered.len() < set.len() {
+        return Err(OrderingError::Cyclic(find_cycle(set, &ordered, rules_map)));
+    }
+    if let Some((page, other)) = ambiguous {
+        return Err(OrderingError::Ambiguous(page, other));
+    }
+    Ok(ordered)
+}
+
+// Finds a cycle among the pages the topological sort got stuck on. Every stuck page still has a
+// stuck page that has to come before it, so following those backwards must eventually loop.
+// Pages that are only stuck because they come after the cycle are left out
+fn find_cycle(
+    set: &[PageId],
+    ordered: &[PageId],
+    rules_map: &HashMap<PageId, HashSet<PageId>>,
+) -> Vec<PageId> {
+    let stuck: Vec<PageId> = set
+        .iter()
+        .copied()
+        .filter(|page| !ordered.contains(page))
+        .collect();
+    let mut path = Vec::new();
+    let mut page = stuck[0];
+    while !path.contains(&page) {
+        path.push(page);
+        page = *stuck
+            .iter()
+            .find(|before| {
+                rules_map
+                    .get(before)
+                    .is_some_and(|after| after.contains(&page))
+            })
+            .expect("every stuck page to have a stuck page before it");
+    }
+    let start = path.iter().position(|&p| p == page).unwrap();
+    let cycle = &path[start..];
+    // Report them in the update's order so results are deterministic
+    stuck
         .into_iter()
-        .map(|set| usize::from(set[set.len() / 2]))
-        .sum();
+        .filter(|page| cycle.contains(page))
+        .collect()
+}
+
+/// Why the puzzle input couldn't be parsed.
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// A rule or update line was malformed. `line_number` is 1-based, to match what an editor
+    /// would show.
+    InvalidLine {
+        line_number: usize,
+        line: String,
+        reason: String,
+    },
+    /// There was no blank line separating the rules from the updates, either because there were
+    /// no updates or because they came straight after the rules.
+    MissingSeparator,
+}
+
+impl ParseError {
+    fn invalid_line(index: usize, line: &str, reason: String) -> Self {
+        ParseError::InvalidLine {
+            line_number: index + 1,
+            line: line.to_owned(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidLine {
+                line_number,
+                line,
+                reason,
+            } => write!(f, "line {}: {} (in {:?})", line_number, reason, line),
+            ParseError::MissingSeparator => {
+                write!(f, "no blank line between the rules and the updates")
+            }
+        }
+    }
+}
+
+fn parse_rule(line: &str) -> Result<(PageId, PageId), String> {
+    let (before, after) = line
+        .split_once('|')
+        .ok_or_else(|| "expected a rule like `A|B`".to_owned())?;
+    Ok((before.parse()?, after.parse()?))
+}
+
+fn parse_update(line: &str) -> Result<Vec<PageId>, String> {
+    line.split(',').map(str::parse).collect()
+}
+
+pub struct Update {
+    pub line_number: usize,
+    pub pages: Vec<PageId>,
+}
+
+fn middle_page(pages: &[PageId]) -> u64 {
+    u64::from(pages[pages.len() / 2].0)
+}
+
+/// The page ordering rules and the list of updates, parsed from the puzzle input.
+pub struct PrintQueue {
+    /// Maps each page to the pages that have to come after it.
+    pub rules: HashMap<PageId, HashSet<PageId>>,
+    pub updates: Vec<Update>,
+}
+
+impl FromStr for PrintQueue {
+    type Err = ParseError;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let mut rules: HashMap<PageId, HashSet<PageId>> = HashMap::new();
+        let mut updates = Vec::new();
+        let mut in_updates = false;
+        for (i, line) in data.lines().enumerate() {
+            if line.trim().is_empty() {
+                // The first blank line separates the two sections, any others are ignored
+                in_updates = true;
+                continue;
+            }
+            if in_updates {
+                let pages = parse_update(line)
+                    .map_err(|reason| ParseError::invalid_line(i, line, reason))?;
+                updates.push(Update {
+                    line_number: i + 1,
+                    pages,
+                });
+            } else {
+                let (before, after) = parse_rule(line).map_err(|reason| {
+                    // An update here means the blank line before the updates was left out
+                    if line.contains(',') && parse_update(line).is_ok() {
+                        ParseError::MissingSeparator
+                    } else {
+                        ParseError::invalid_line(i, line, reason)
+                    }
+                })?;
+                // Add `after` to the list of numbers that cannot be before `before`
+                rules.entry(before).or_default().insert(after);
+            }
+        }
+        if !in_updates {
+            return Err(ParseError::MissingSeparator);
+        }
+        Ok(PrintQueue { rules, updates })
+    }
+}
+
+impl PrintQueue {
+    /// Sum of the middle pages of the updates that are already in the right order.
+    pub fn part_one(&self) -> u64 {
+        self.updates
+            .iter()
+            .filter(|update| set_is_valid(&update.pages, &self.rules))
+            .map(|update| middle_page(&update.pages))
+            .sum()
+    }
+
+    /// Sum of the middle pages of the incorrect updates once they've been put in the right
+    /// order. If `explain` is set, also prints why each incorrect update was wrong.
+    pub fn part_two(&self, explain: bool) -> u64 {
+        let mut total = 0;
+        for update in &self.updates {
+            if set_is_valid(&update.pages, &self.rules) {
+                continue;
+            }
+            let fixed = order_update(&update.pages, &self.rules);
+            if explain {
+                println!("Update on line {}:", update.line_number);
+                print!("{}", explain_update(&update.pages, &fixed, &self.rules));
+            }
+            match fixed {
+                Ok(pages) => total += middle_page(&pages),
+                Err(e) => eprintln!("Couldn't fix update on line {}: {}", update.line_number, e),
+            }
+        }
+        total
+    }
+}
+
+/// Solves both parts, and if `explain` is set also prints why each incorrect update was wrong.
+pub fn solve(data: &str, explain: bool) -> Result<(), ParseError> {
+    let queue: PrintQueue = data.parse()?;
+    println!(
+        "Total of middle numbers of correct sets is {}",
+        queue.part_one()
+    );
     println!(
         "Total of middle numbers of fixed incorrect sets is {}",
-        answer_two
+        queue.part_two(explain)
     );
+    Ok(())
 }
 
 pub fn solution() {
     let raw_data = fs::read_to_string("input/day5input.txt").expect("Failed to read input file!");
-    solve(&raw_data);
+    if let Err(e) = solve(&raw_data, has_flag("--explain")) {
+        eprintln!("Invalid day 5 input: {}", e);
+    }
+}
+
+#[cfg(test)]
+fn rules_from(rules: &[(u32, u32)]) -> HashMap<PageId, HashSet<PageId>> {
+    let mut rules_map: HashMap<PageId, HashSet<PageId>> = HashMap::new();
+    for &(before, after) in rules {
+        rules_map
+            .entry(PageId(before))
+            .or_default()
+            .insert(PageId(after));
+    }
+    rules_map
+}
+
+#[cfg(test)]
+fn pages(pages: &[u32]) -> Vec<PageId> {
+    pages.iter().copied().map(PageId).collect()
+}
+
+#[test]
+fn orders_updates() {
+    let rules_map = rules_from(&[
+        (47, 53),
+        (97, 13),
+        (97, 61),
+        (97, 47),
+        (75, 29),
+        (61, 13),
+        (75, 53),
+        (29, 13),
+        (97, 29),
+        (53, 29),
+        (61, 53),
+        (97, 53),
+        (61, 29),
+        (47, 13),
+        (75, 47),
+        (97, 75),
+        (47, 61),
+        (75, 61),
+        (47, 29),
+        (75, 13),
+        (53, 13),
+    ]);
+    assert_eq!(
+        order_update(&pages(&[75, 97, 47, 61, 53]), &rules_map),
+        Ok(pages(&[97, 75, 47, 61, 53]))
+    );
+    assert_eq!(
+        order_update(&pages(&[61, 13, 29]), &rules_map),
+        Ok(pages(&[61, 29, 13]))
+    );
+    assert_eq!(
+        order_update(&pages(&[97, 13, 75, 29, 47]), &rules_map),
+        Ok(pages(&[97, 75, 47, 29, 13]))
+    );
+}
+
+#[test]
+fn reports_unorderable_updates() {
+    let rules_map = rules_from(&[(1, 2), (2, 3), (3, 1), (4, 5)]);
+    assert_eq!(
+        order_update(&pages(&[4, 1, 2, 3]), &rules_map),
+        Err(OrderingError::Cyclic(pages(&[1, 2, 3])))
+    );
+    assert_eq!(
+        order_update(&pages(&[5, 4, 6]), &rules_map),
+        Err(OrderingError::Ambiguous(PageId(4), PageId(6)))
+    );
+    assert_eq!(
+        order_update(&pages(&[2, 1, 2]), &rules_map),
+        Err(OrderingError::DuplicatePage(PageId(2)))
+    );
+
+    // A cycle is reported even when other pages are ambiguous, and pages that only come after the
+    // cycle aren't part of it
+    assert_eq!(
+        order_update(&pages(&[4, 6, 1, 2, 3]), &rules_map),
+        Err(OrderingError::Cyclic(pages(&[1, 2, 3])))
+    );
+    let rules_map = rules_from(&[(1, 2), (2, 3), (3, 1), (3, 6), (6, 7)]);
+    assert_eq!(
+        order_update(&pages(&[7, 4, 6, 1, 2, 3]), &rules_map),
+        Err(OrderingError::Cyclic(pages(&[1, 2, 3])))
+    );
+}
+
+#[test]
+fn parses_any_page_width() {
+    assert_eq!(parse_rule("5|1234"), Ok((PageId(5), PageId(1234))));
+    assert_eq!(parse_rule(" 300 | 7 "), Ok((PageId(300), PageId(7))));
+    assert_eq!(parse_update("1, 22 ,333"), Ok(pages(&[1, 22, 333])));
+    assert!(parse_rule("12-34").is_err());
+    assert!(parse_update("1,,2").is_err());
+}
+
+#[test]
+fn parse_errors_point_to_line() {
+    let error = "1|2\n3|x\n\n1,2\n".parse::<PrintQueue>().err();
+    assert!(matches!(
+        error,
+        Some(ParseError::InvalidLine { line_number: 2, ref line, .. }) if line == "3|x"
+    ));
+    let error = "1|2\n\n1,2\n2;1\n".parse::<PrintQueue>().err();
+    assert!(matches!(
+        error,
+        Some(ParseError::InvalidLine { line_number: 4, .. })
+    ));
+    let error = "1|2\n3|4\n".parse::<PrintQueue>().err();
+    assert_eq!(error, Some(ParseError::MissingSeparator));
+    let error = "1|2\n3|4\n1,2,3\n".parse::<PrintQueue>().err();
+    assert_eq!(error, Some(ParseError::MissingSeparator));
+}
+
+#[test]
+fn example_answers() {
+    let queue: PrintQueue = "47|53\n97|13\n97|61\n97|47\n75|29\n61|13\n75|53\n29|13\n97|29\n\
+        53|29\n61|53\n97|53\n61|29\n47|13\n75|47\n97|75\n47|61\n75|61\n47|29\n75|13\n53|13\n\
+        \n75,47,61,53,29\n97,61,53,29,13\n75,29,13\n75,97,47,61,53\n61,13,29\n97,13,75,29,47\n"
+        .parse()
+        .expect("example input to parse");
+    assert_eq!(queue.part_one(), 143);
+    assert_eq!(queue.part_two(false), 123);
+}
+
+#[test]
+fn explains_violations() {
+    let rules_map = rules_from(&[(97, 75), (97, 13), (75, 13), (29, 13), (75, 29), (97, 29)]);
+    let set = pages(&[97, 13, 75, 29]);
+    let fixed = order_update(&set, &rules_map);
+    assert_eq!(
+        explain_update(&set, &fixed, &rules_map),
+        "  breaks 75|13: 75 at position 2 comes after 13 at position 1
+  breaks 29|13: 29 at position 3 comes after 13 at position 1
+  pos  original  fixed
+    0        97     97
+    1        13     75 *
+    2        75     29 *
+    3        29     13 *
+"
+    );
 }
@@ -1,7 +1,15 @@
-use std::{env::current_exe, fs};
+use crate::util::{flag_value, has_flag, ANSI_CLEAR};
+use bitflags::bitflags;
+use proptest::proptest;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::num::NonZeroUsize;
+use std::thread;
+use std::time::Duration;
 
-#[derive(Debug, Clone)]
-enum Direction {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
     Up,
     Right,
     Down,
@@ -9,7 +17,7 @@ enum Direction {
 }
 
 impl Direction {
-    fn to_offset(&self) -> (i32, i32) {
+    fn to_offset(self) -> (i32, i32) {
         match self {
             Direction::Up => (0, -1),
             Direction::Right => (1, 0),
@@ -17,6 +25,23 @@ impl Direction {
             Direction::Left => (-1, 0),
         }
     }
+    // Position of this direction in per-direction tables
+    fn index(self) -> usize {
+        match self {
+            Direction::Up => 0,
+            Direction::Right => 1,
+            Direction::Down => 2,
+            Direction::Left => 3,
+        }
+    }
+    fn flag(self) -> Directions {
+        match self {
+            Direction::Up => Directions::UP,
+            Direction::Right => Directions::RIGHT,
+            Direction::Down => Directions::DOWN,
+            Direction::Left => Directions::LEFT,
+        }
+    }
     fn turn_clockwise(&self) -> Self {
         match self {
             Direction::Up => Direction::Right,
@@ -25,39 +50,100 @@ impl Direction {
             Direction::Left => Direction::Up,
         }
     }
+    fn turn_around(&self) -> Self {
+        self.turn_clockwise().turn_clockwise()
+    }
+    fn turn_counter_clockwise(&self) -> Self {
+        self.turn_around().turn_clockwise()
+    }
+    // The character used for a guard facing this way on the map
+    fn marker(self) -> char {
+        match self {
+            Direction::Up => '^',
+            Direction::Right => '>',
+            Direction::Down => 'v',
+            Direction::Left => '<',
+        }
+    }
+}
+
+/// Which way a guard turns when there's an obstacle in front of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Turn {
+    #[default]
+    Clockwise,
+    CounterClockwise,
+    Around,
+}
+
+impl Turn {
+    fn apply(self, direction: Direction) -> Direction {
+        match self {
+            Turn::Clockwise => direction.turn_clockwise(),
+            Turn::CounterClockwise => direction.turn_counter_clockwise(),
+            Turn::Around => direction.turn_around(),
+        }
+    }
+}
+
+/// How guards move around the lab. The default is the puzzle's: turn right at obstacles and
+/// leave when walking off the edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GuardRules {
+    pub turn: Turn,
+    /// Walking off one edge of the map comes back in on the opposite edge, so the only way a
+    /// patrol can end is in a loop.
+    pub wrap: bool,
+}
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Right,
+    Direction::Down,
+    Direction::Left,
+];
+
+bitflags! {
+    /// A set of directions, used to track which ways the guard has faced at a position.
+    #[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+    struct Directions: u8 {
+        const UP = 1;
+        const RIGHT = 1 << 1;
+        const DOWN = 1 << 2;
+        const LEFT = 1 << 3;
+    }
+}
+
+impl Directions {
+    // How a cell the guard has walked through is drawn in the puzzle's illustrations: `|` for
+    // up and down, `-` for left and right, and `+` where the two cross or the guard turned
+    fn path_char(self) -> Option<char> {
+        let vertical = self.intersects(Directions::UP | Directions::DOWN);
+        let horizontal = self.intersects(Directions::LEFT | Directions::RIGHT);
+        match (vertical, horizontal) {
+            (true, true) => Some('+'),
+            (true, false) => Some('|'),
+            (false, true) => Some('-'),
+            (false, false) => None,
+        }
+    }
 }
 
 #[derive(Default, Clone)]
 struct MapPosition {
     is_obstacle: bool,
-    already_visited_up: bool,
-    already_visited_right: bool,
-    already_visited_down: bool,
-    already_visited_left: bool,
+    visited: Directions,
 }
 
 impl MapPosition {
     fn already_visited(&self) -> bool {
-        self.already_visited_up
-            || self.already_visited_down
-            || self.already_visited_left
-            || self.already_visited_right
+        !self.visited.is_empty()
     }
     fn already_visited_dir(&self, dir: &Direction) -> bool {
-        match dir {
-            Direction::Up => self.already_visited_up,
-            Direction::Right => self.already_visited_right,
-            Direction::Down => self.already_visited_down,
-            Direction::Left => self.already_visited_left,
-        }
+        self.visited.contains(dir.flag())
     }
     fn set_already_visited(&mut self, dir: &Direction) {
-        match dir {
-            Direction::Up => self.already_visited_up = true,
-            Direction::Right => self.already_visited_right = true,
-            Direction::Down => self.already_visited_down = true,
-            Direction::Left => self.already_visited_left = true,
-        }
+        self.visited.insert(dir.flag());
     }
 }
 
@@ -72,153 +158,904 @@ fn get_position(map: &mut [Vec<MapPosition>], x: i32, y: i32) -> Option<&mut Map
     map.get_mut(yr)?.get_mut(xr)
 }
 
-fn check_for_obstacle(
-    orig_map: &[Vec<MapPosition>],
+/// How a patrol ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// On move `step` the guard walked off the edge of the map.
+    Exited { step: usize },
+    /// On move `step` the guard ended up somewhere it had already been, facing the same way, so
+    /// it will keep walking the same loop forever.
+    Looped { step: usize },
+}
+
+/// A guard patrolling the lab, which can be stepped through one move at a time. A move is
+/// either a step forward or a turn on the spot.
+#[derive(Clone)]
+pub struct GuardSimulation {
+    map: Vec<Vec<MapPosition>>,
+    rules: GuardRules,
     x: i32,
     y: i32,
-    input_dir: &Direction,
-) -> bool {
-    // Simulate what would happen if there was an obstacle in front of us at this position
-    // Clone the map to keep our own state just for this test
-    let mut map = orig_map.to_vec().clone();
-    let mut current_dir = input_dir.clone();
-    let mut cur_x = x;
-    let mut cur_y = y;
-    let mut first_iter = true;
-    loop {
-        let next_x = cur_x + current_dir.to_offset().0;
-        let next_y = cur_y + current_dir.to_offset().1;
-        if let Some(pos) = get_position(&mut map,
-            next_x,
-            next_y
-        ) {
-            if first_iter {
-                // Check if our test position has already been walked over
-                // If so, that means an obstacle can't go here
-                if pos.already_visited() {
-                    return false;
-                }
-                if !pos.is_obstacle {
-                    // Place our loop obstacle
-                    pos.is_obstacle = true;
-                }
-                first_iter = false;
+    direction: Direction,
+    steps: usize,
+    outcome: Option<Outcome>,
+}
+
+impl GuardSimulation {
+    fn new(
+        mut map: Vec<Vec<MapPosition>>,
+        rules: GuardRules,
+        x: i32,
+        y: i32,
+        direction: Direction,
+    ) -> Self {
+        // Starting position is always already visited
+        get_position(&mut map, x, y)
+            .expect("guard to start on the map")
+            .set_already_visited(&direction);
+        GuardSimulation {
+            map,
+            rules,
+            x,
+            y,
+            direction,
+            steps: 0,
+            outcome: None,
+        }
+    }
+
+    pub fn position(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// `None` while the guard is still patrolling.
+    pub fn outcome(&self) -> Option<&Outcome> {
+        self.outcome.as_ref()
+    }
+
+    /// Every position the guard has been to so far.
+    pub fn visited(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.map.iter().enumerate().flat_map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .filter(|(_, pos)| pos.already_visited())
+                .map(move |(x, _)| (x, y))
+        })
+    }
+
+    // The position the guard would walk onto next, which might be off the map
+    fn ahead(&self) -> (i32, i32) {
+        let (offset_x, offset_y) = self.direction.to_offset();
+        let (x, y) = (self.x + offset_x, self.y + offset_y);
+        if self.rules.wrap {
+            let height = self.map.len() as i32;
+            let width = self.map[self.y as usize].len() as i32;
+            (x.rem_euclid(width), y.rem_euclid(height))
+        } else {
+            (x, y)
+        }
+    }
+
+    /// Makes a single move, returning the outcome if that move ended the patrol.
+    pub fn step(&mut self) -> Option<&Outcome> {
+        if self.outcome.is_some() {
+            return self.outcome.as_ref();
+        }
+        self.steps += 1;
+        let (next_x, next_y) = self.ahead();
+        match get_position(&mut self.map, next_x, next_y) {
+            None => {
+                self.outcome = Some(Outcome::Exited { step: self.steps });
+                return self.outcome.as_ref();
             }
-            if pos.is_obstacle {
-                pos.set_already_visited(&current_dir);
-                current_dir = current_dir.turn_clockwise();
-            } else if pos.already_visited_dir(&current_dir) {
-                // If we ever end up in a spot we've already been facing a direction we've already faced at
-                // that spot, we have a loop and the obstacle is valid
-                return true;
-            } else {
-                pos.set_already_visited(&current_dir);
-                cur_x = next_x;
-                cur_y = next_y;
+            Some(pos) if pos.is_obstacle => self.direction = self.rules.turn.apply(self.direction),
+            Some(_) => {
+                self.x = next_x;
+                self.y = next_y;
             }
-        // otherwise, we went out of bounds with either x or y, so the obstacle did not cause a loop
+        }
+        let pos = get_position(&mut self.map, self.x, self.y).expect("guard to be on the map");
+        if pos.already_visited_dir(&self.direction) {
+            self.outcome = Some(Outcome::Looped { step: self.steps });
         } else {
-            return false;
+            pos.set_already_visited(&self.direction);
         }
+        self.outcome.as_ref()
     }
-}
 
-#[derive(Debug)]
-enum PatrolSection {
-    Stayed(i32, i32),
-    Left(i32),
+    /// Keeps moving until the guard either leaves the map or gets stuck in a loop.
+    pub fn run(&mut self) -> &Outcome {
+        while self.step().is_none() {}
+        self.outcome.as_ref().expect("patrol to have ended")
+    }
 }
 
-fn distance_to_obstacle(
-    map: &mut [Vec<MapPosition>],
-    start_x: i32,
-    start_y: i32,
-    dir: &Direction,
-    possible_obstacle_count: &mut i32,
-) -> PatrolSection {
-    let mut distance = 0;
-    let mut unrepeated_distance = 0;
-    let mut cur_x = start_x;
-    let mut cur_y = start_y;
-    loop {
-        if check_for_obstacle(map, cur_x, cur_y, dir) {
-            *possible_obstacle_count += 1;
+// Reads the map, returning a simulation for each guard on it in reading order. Guards don't get
+// in each other's way, so each one patrols the map as if it were alone. The map has to be
+// rectangular, apart from any blank lines at the end
+fn parse_map(raw_data: &str, rules: GuardRules) -> Result<Vec<GuardSimulation>, String> {
+    let mut lines: Vec<&str> = raw_data.lines().collect();
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    let width = lines.first().map_or(0, |line| line.chars().count());
+    let mut starts = Vec::new();
+    let mut map: Vec<Vec<MapPosition>> = Vec::with_capacity(lines.len());
+    for (y, line) in lines.into_iter().enumerate() {
+        let mut row = Vec::with_capacity(width);
+        for (x, char) in line.chars().enumerate() {
+            let mut out = MapPosition::default();
+            match char {
+                '#' => out.is_obstacle = true,
+                '.' => {}
+                c => {
+                    let direction = DIRECTIONS
+                        .into_iter()
+                        .find(|direction| direction.marker() == c)
+                        .ok_or_else(|| format!("unexpected character '{}'", c))?;
+                    starts.push((
+                        x.try_into().expect("array index to always convert to i32"),
+                        y.try_into().expect("array index to always convert to i32"),
+                        direction,
+                    ));
+                }
+            }
+            row.push(out);
         }
-        cur_x += dir.to_offset().0;
-        cur_y += dir.to_offset().1;
-        if let Some(pos) = get_position(
-            map,
-            cur_x,
-            cur_y
-        ) {
-            if pos.is_obstacle {
-                return PatrolSection::Stayed(distance, unrepeated_distance);
-            } else {
-                if !pos.already_visited() {
-                    pos.set_already_visited(dir);
-                    unrepeated_distance += 1;
+        if row.len() != width {
+            return Err(format!(
+                "line {} is {} cells wide, but the map is {} wide",
+                y + 1,
+                row.len(),
+                width
+            ));
+        }
+        map.push(row);
+    }
+    if starts.is_empty() {
+        return Err("there's no guard on the map".to_owned());
+    }
+    Ok(starts
+        .into_iter()
+        .map(|(x, y, direction)| GuardSimulation::new(map.clone(), rules, x, y, direction))
+        .collect())
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Jump {
+    // Walking forward ends at this cell (as an index into the map), facing an obstacle
+    Stop(usize),
+    // Walking forward leaves the map
+    Exit,
+}
+
+/// For every cell and direction, where the guard ends up if it walks forward until it hits
+/// something. This lets a whole straight line of the patrol be skipped in one go. Assumes every
+/// row of the map is the same length, like the puzzle input.
+struct JumpTable {
+    width: usize,
+    height: usize,
+    jumps: Vec<[Jump; 4]>,
+}
+
+impl JumpTable {
+    fn new(map: &[Vec<MapPosition>]) -> Self {
+        let height = map.len();
+        let width = map.first().map_or(0, |row| row.len());
+        let mut jumps = vec![[Jump::Exit; 4]; width * height];
+        for direction in DIRECTIONS {
+            let (offset_x, offset_y) = direction.to_offset();
+            // Sweep backwards against the direction of travel, remembering the last place we
+            // could have stopped in front of an obstacle
+            for line in 0..(if offset_x == 0 { width } else { height }) {
+                let mut stop = Jump::Exit;
+                let len = if offset_x == 0 { height } else { width };
+                for i in 0..len {
+                    // Index along the line, starting from the end the guard would walk towards
+                    let along = if offset_x + offset_y < 0 {
+                        i
+                    } else {
+                        len - 1 - i
+                    };
+                    let (x, y) = if offset_x == 0 {
+                        (line, along)
+                    } else {
+                        (along, line)
+                    };
+                    if map[y][x].is_obstacle {
+                        let stop_x = x as i32 - offset_x;
+                        let stop_y = y as i32 - offset_y;
+                        stop = if (0..width as i32).contains(&stop_x)
+                            && (0..height as i32).contains(&stop_y)
+                        {
+                            Jump::Stop(stop_y as usize * width + stop_x as usize)
+                        } else {
+                            Jump::Exit
+                        };
+                    } else {
+                        jumps[y * width + x][direction.index()] = stop;
+                    }
                 }
-                distance += 1;
             }
-        // otherwise, we went out of bounds with either x or y, so return Left
+        }
+        JumpTable {
+            width,
+            height,
+            jumps,
+        }
+    }
+
+    fn coords(&self, cell: usize) -> (i32, i32) {
+        ((cell % self.width) as i32, (cell / self.width) as i32)
+    }
+}
+
+/// Somewhere an extra obstacle could go, along with where the guard is the move before it would
+/// first walk onto that spot.
+#[derive(Clone, Copy, Debug)]
+struct Candidate {
+    // Which of the guards walks onto this spot
+    guard: usize,
+    obstacle: (i32, i32),
+    x: i32,
+    y: i32,
+    direction: Direction,
+}
+
+/// Checks whether extra obstacles would trap the guard in a loop, by jumping between the places
+/// the guard turns rather than walking every step.
+struct LoopChecker<'a> {
+    table: &'a JumpTable,
+    turn: Turn,
+    // Directions the guard has turned away from at each cell during the current check
+    turned: Vec<Directions>,
+    // Cells written to in `turned`, so only they need clearing between checks
+    touched: Vec<usize>,
+}
+
+impl<'a> LoopChecker<'a> {
+    fn new(table: &'a JumpTable, turn: Turn) -> Self {
+        LoopChecker {
+            table,
+            turn,
+            turned: vec![Directions::empty(); table.width * table.height],
+            touched: Vec::new(),
+        }
+    }
+
+    // Where the guard stops walking from (x, y), taking the extra obstacle into account
+    fn jump(&self, x: i32, y: i32, direction: Direction, obstacle: (i32, i32)) -> Jump {
+        let (offset_x, offset_y) = direction.to_offset();
+        let cell = y as usize * self.table.width + x as usize;
+        let jump = self.table.jumps[cell][direction.index()];
+        // How far ahead the extra obstacle is, if it's in the way at all
+        let (to_obstacle_x, to_obstacle_y) = (obstacle.0 - x, obstacle.1 - y);
+        let in_line = if offset_x == 0 {
+            to_obstacle_x == 0
+        } else {
+            to_obstacle_y == 0
+        };
+        let obstacle_distance = to_obstacle_x * offset_x + to_obstacle_y * offset_y;
+        if !in_line || obstacle_distance <= 0 {
+            return jump;
+        }
+        let closer = match jump {
+            Jump::Exit => true,
+            Jump::Stop(stop) => {
+                let (stop_x, stop_y) = self.table.coords(stop);
+                obstacle_distance - 1 < (stop_x - x) * offset_x + (stop_y - y) * offset_y
+            }
+        };
+        if closer {
+            let stop_x = obstacle.0 - offset_x;
+            let stop_y = obstacle.1 - offset_y;
+            Jump::Stop(stop_y as usize * self.table.width + stop_x as usize)
         } else {
-            return PatrolSection::Left(unrepeated_distance);
+            jump
+        }
+    }
+
+    fn causes_loop(&mut self, candidate: &Candidate) -> bool {
+        for cell in self.touched.drain(..) {
+            self.turned[cell] = Directions::empty();
+        }
+        let (mut x, mut y) = (candidate.x, candidate.y);
+        let mut direction = candidate.direction;
+        loop {
+            match self.jump(x, y, direction, candidate.obstacle) {
+                Jump::Exit => return false,
+                Jump::Stop(cell) => {
+                    // Turning the same way at the same spot twice means we're going round in
+                    // circles
+                    if self.turned[cell].contains(direction.flag()) {
+                        return true;
+                    }
+                    self.turned[cell].insert(direction.flag());
+                    self.touched.push(cell);
+                    (x, y) = self.table.coords(cell);
+                    direction = self.turn.apply(direction);
+                }
+            }
         }
     }
 }
 
-fn solve(raw_data: &str) {
-    let mut cur_x: i32 = 0;
-    let mut cur_y: i32 = 0;
-    let mut cur_dir = Direction::Up;
-    let mut map: Vec<Vec<MapPosition>> = raw_data
-        .lines()
+// Walks the guard's patrol, noting every spot it's about to walk onto for the first time. If the
+// guard's already walked over a spot, an obstacle can't go there as it would have changed the
+// path that got the guard here
+fn find_candidates(guard: usize, mut sim: GuardSimulation) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    while sim.outcome().is_none() {
+        let (x, y) = sim.position();
+        let direction = sim.direction();
+        let obstacle = sim.ahead();
+        if let Some(pos) = get_position(&mut sim.map, obstacle.0, obstacle.1) {
+            if !pos.is_obstacle && !pos.already_visited() {
+                candidates.push(Candidate {
+                    guard,
+                    obstacle,
+                    x,
+                    y,
+                    direction,
+                });
+            }
+        }
+        sim.step();
+    }
+    candidates
+}
+
+// Candidates from every guard's patrol. An obstacle can't go where a guard is standing at the
+// start, and only traps a guard if it's somewhere that guard walks onto
+fn all_candidates(guards: &[GuardSimulation]) -> Vec<Candidate> {
+    let starts: Vec<_> = guards.iter().map(|guard| guard.position()).collect();
+    guards
+        .iter()
         .enumerate()
-        .map(|(y, l)| {
-            l.chars()
-                .enumerate()
-                .map(|(x, char)| {
-                    let mut out = MapPosition::default();
-                    match char {
-                        '^' => {
-                            cur_x = x.try_into().expect("array index to always convert to i32");
-                            cur_y = y.try_into().expect("array index to always convert to i32");
-                            // Starting position is always already visited
-                            out.already_visited_up = true;
-                        }
-                        '#' => out.is_obstacle = true,
-                        '.' => {}
-                        c => panic!("Unexpected character '{}'", c),
-                    }
-                    out
+        .flat_map(|(i, guard)| find_candidates(i, guard.clone()))
+        .filter(|candidate| !starts.contains(&candidate.obstacle))
+        .collect()
+}
+
+// Finds every spot where an extra obstacle traps at least one of the guards in a loop, along with
+// the first of the guards it traps. Everything a guard did before reaching a candidate still
+// happens with the obstacle in place, so each check can start from just in front of it. The
+// checks don't depend on each other, so they're split between `threads` workers, each with its
+// own scratch space
+fn find_loop_obstacles(
+    guards: &[GuardSimulation],
+    threads: NonZeroUsize,
+) -> BTreeMap<(i32, i32), usize> {
+    let Some(first) = guards.first() else {
+        return BTreeMap::new();
+    };
+    let candidates = all_candidates(guards);
+    // When wrapping there's no way off the map, so a guard is always stuck in some loop
+    if first.rules.wrap {
+        return by_obstacle(candidates.into_iter());
+    }
+    let table = JumpTable::new(&first.map);
+    let turn = first.rules.turn;
+    let chunk_size = candidates.len().div_ceil(threads.get()).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = candidates
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let table = &table;
+                scope.spawn(move || {
+                    let mut checker = LoopChecker::new(table, turn);
+                    chunk
+                        .iter()
+                        .filter(|candidate| checker.causes_loop(candidate))
+                        .copied()
+                        .collect::<Vec<_>>()
                 })
-                .collect()
-        })
+            })
+            .collect();
+        let trapping = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("loop checking thread to not panic"));
+        by_obstacle(trapping)
+    })
+}
+
+// Candidates are in guard order, so keeping the first one for each spot keeps the first guard
+fn by_obstacle(candidates: impl Iterator<Item = Candidate>) -> BTreeMap<(i32, i32), usize> {
+    let mut obstacles = BTreeMap::new();
+    for candidate in candidates {
+        obstacles
+            .entry(candidate.obstacle)
+            .or_insert(candidate.guard);
+    }
+    obstacles
+}
+
+// The original approach of playing out the whole patrol on a copy of the map for every
+// candidate, kept to check the fast version against
+#[cfg(test)]
+fn find_loop_obstacles_slow(guards: &[GuardSimulation]) -> BTreeMap<(i32, i32), usize> {
+    by_obstacle(
+        all_candidates(guards)
+            .into_iter()
+            .filter(|candidate| loop_cycle(&guards[candidate.guard], candidate.obstacle).is_some()),
+    )
+}
+
+/// Plays out the patrol with an extra obstacle at `obstacle`, and if the guard gets stuck
+/// returns the positions and directions it keeps repeating, starting from where it first
+/// entered the loop.
+fn loop_cycle(sim: &GuardSimulation, obstacle: (i32, i32)) -> Option<Vec<((i32, i32), Direction)>> {
+    let mut test = sim.clone();
+    get_position(&mut test.map, obstacle.0, obstacle.1)
+        .expect("obstacle to be on the map")
+        .is_obstacle = true;
+    let mut history = vec![(test.position(), test.direction())];
+    while test.step().is_none() {
+        history.push((test.position(), test.direction()));
+    }
+    match test.outcome() {
+        Some(Outcome::Looped { .. }) => {
+            // The state the guard just got back to is where the cycle starts
+            let repeated = (test.position(), test.direction());
+            let start = history
+                .iter()
+                .position(|&state| state == repeated)
+                .expect("looped state to have been seen before");
+            Some(history.split_off(start))
+        }
+        _ => None,
+    }
+}
+
+/// Draws the map with the guards where they start, each of `obstacles` shown as `O` and `path`
+/// traced over it, like the illustrations in the puzzle.
+fn render_map(
+    guards: &[GuardSimulation],
+    obstacles: &BTreeSet<(i32, i32)>,
+    path: &[((i32, i32), Direction)],
+) -> String {
+    let mut path_cells: Vec<Vec<Directions>> = guards[0]
+        .map
+        .iter()
+        .map(|row| vec![Directions::empty(); row.len()])
         .collect();
-    // distance starts at 1 since the starting location counts
-    let mut unique_distance = 1;
-    let mut obstacle_count = 0;
-    loop {
-        match distance_to_obstacle(&mut map, cur_x, cur_y, &cur_dir, &mut obstacle_count) {
-            PatrolSection::Stayed(total_dis, unique_dis) => {
-                unique_distance += unique_dis;
-                // Modify our x and y by adding our offset multiplied by the distance travelled
-                cur_x += cur_dir.to_offset().0 * total_dis;
-                cur_y += cur_dir.to_offset().1 * total_dis;
-                // Turn
-                cur_dir = cur_dir.turn_clockwise();
-            }
-            PatrolSection::Left(unique_dis) => {
-                unique_distance += unique_dis;
-                break;
+    for &((x, y), direction) in path {
+        path_cells[y as usize][x as usize].insert(direction.flag());
+    }
+    draw_map(guards, obstacles, &path_cells)
+}
+
+/// Draws the map with the guards where they are now and everywhere they've been so far.
+fn render_patrol(guards: &[GuardSimulation]) -> String {
+    let mut path_cells: Vec<Vec<Directions>> = guards[0]
+        .map
+        .iter()
+        .map(|row| row.iter().map(|pos| pos.visited).collect())
+        .collect();
+    for guard in &guards[1..] {
+        for (path_row, row) in path_cells.iter_mut().zip(&guard.map) {
+            for (cell, pos) in path_row.iter_mut().zip(row) {
+                *cell |= pos.visited;
             }
         }
     }
-    println!("Total distance patrolled is {}", unique_distance);
-    println!("Obstacle count is {}", obstacle_count);
+    draw_map(guards, &BTreeSet::new(), &path_cells)
+}
+
+// Guards that have left the map aren't drawn
+fn draw_map(
+    guards: &[GuardSimulation],
+    obstacles: &BTreeSet<(i32, i32)>,
+    path_cells: &[Vec<Directions>],
+) -> String {
+    let mut out = String::new();
+    for (y, row) in guards[0].map.iter().enumerate() {
+        for (x, pos) in row.iter().enumerate() {
+            let coords = (x as i32, y as i32);
+            let guard = guards.iter().find(|guard| {
+                guard.position() == coords
+                    && !matches!(guard.outcome(), Some(Outcome::Exited { .. }))
+            });
+            out.push(if let Some(guard) = guard {
+                guard.direction().marker()
+            } else if pos.is_obstacle {
+                '#'
+            } else if obstacles.contains(&coords) {
+                'O'
+            } else {
+                path_cells[y][x].path_char().unwrap_or('.')
+            });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Plays out the guards' patrols together one move at a time, writing the map to `out` before
+/// the first move and after every move, until every guard has left or is stuck in a loop. Waits
+/// `delay` between frames, and if `clear` is set clears the terminal before each one so the map
+/// is redrawn in place.
+fn animate(
+    mut guards: Vec<GuardSimulation>,
+    out: &mut impl Write,
+    delay: Duration,
+    clear: bool,
+) -> io::Result<()> {
+    let mut moves = 0;
+    loop {
+        if clear {
+            write!(out, "{}", ANSI_CLEAR)?;
+        }
+        writeln!(out, "Move {}\n{}", moves, render_patrol(&guards))?;
+        out.flush()?;
+        if guards.iter().all(|guard| guard.outcome().is_some()) {
+            return Ok(());
+        }
+        thread::sleep(delay);
+        for guard in &mut guards {
+            guard.step();
+        }
+        moves += 1;
+    }
+}
+
+fn solve(raw_data: &str, rules: GuardRules, threads: NonZeroUsize) {
+    let guards = match parse_map(raw_data, rules) {
+        Ok(guards) => guards,
+        Err(e) => {
+            eprintln!("Invalid day 6 input: {}", e);
+            return;
+        }
+    };
+    // Pass `--animate` to watch the guards walk around, waiting `--delay MS` between moves, or
+    // `--frames FILE` to write every frame to a file instead
+    if let Some(path) = flag_value("--frames") {
+        let mut file = BufWriter::new(File::create(path).expect("Failed to create frames file!"));
+        animate(guards.clone(), &mut file, Duration::ZERO, false)
+            .expect("Failed to write frames file!");
+    } else if has_flag("--animate") {
+        let delay = flag_value("--delay").map_or(100, |delay| {
+            delay.parse().expect("delay to be a number of milliseconds")
+        });
+        animate(
+            guards.clone(),
+            &mut io::stdout(),
+            Duration::from_millis(delay),
+            true,
+        )
+        .expect("Failed to write to the terminal!");
+    }
+    let mut visited = BTreeSet::new();
+    for guard in &guards {
+        let mut patrol = guard.clone();
+        patrol.run();
+        visited.extend(patrol.visited());
+    }
+    println!("Total distance patrolled is {}", visited.len());
+    let obstacles = find_loop_obstacles(&guards, threads);
+    println!("Obstacle count is {}", obstacles.len());
+    // Pass `--obstacles` to see where the obstacles go, and `--cycles` to also see the loop each
+    // one traps a guard in
+    if has_flag("--obstacles") || has_flag("--cycles") {
+        let positions: BTreeSet<_> = obstacles.keys().copied().collect();
+        println!("{:?}", positions);
+        print!("{}", render_map(&guards, &positions, &[]));
+    }
+    if has_flag("--cycles") {
+        for (&obstacle, &guard) in &obstacles {
+            let cycle = loop_cycle(&guards[guard], obstacle).expect("obstacle to cause a loop");
+            println!(
+                "\nObstacle at {:?} loops every {} moves",
+                obstacle,
+                cycle.len()
+            );
+            print!(
+                "{}",
+                render_map(&guards, &BTreeSet::from([obstacle]), &cycle)
+            );
+        }
+    }
+}
+
+fn parse_turn(turn: &str) -> Turn {
+    match turn {
+        "clockwise" => Turn::Clockwise,
+        "counter-clockwise" => Turn::CounterClockwise,
+        "around" => Turn::Around,
+        t => panic!("Unknown turn '{}'", t),
+    }
 }
 
 pub fn solution() {
     let raw_data = fs::read_to_string("input/day6input.txt").expect("Failed to read input file!");
-    solve(&raw_data);
+    // Pass `--turn clockwise|counter-clockwise|around` to change which way guards turn at
+    // obstacles, and `--wrap` to have them come back in on the far side when leaving the map
+    let rules = GuardRules {
+        turn: flag_value("--turn").map_or(Turn::default(), |turn| parse_turn(&turn)),
+        wrap: has_flag("--wrap"),
+    };
+    // Pass `--threads N` to choose how many threads check obstacle placements, otherwise one
+    // per available core is used
+    let threads = match flag_value("--threads") {
+        Some(threads) => threads
+            .parse()
+            .expect("thread count to be a positive number"),
+        None => thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+    };
+    solve(&raw_data, rules, threads);
+}
+
+#[cfg(test)]
+const EXAMPLE: &str = "....#.....
+.........#
+..........
+..#.......
+.......#..
+..........
+.#..^.....
+........#.
+#.........
+......#...
+";
+
+// The only guard on a map, following the puzzle's rules
+#[cfg(test)]
+fn single_guard(raw_data: &str) -> GuardSimulation {
+    let mut guards = parse_map(raw_data, GuardRules::default()).unwrap();
+    assert_eq!(guards.len(), 1);
+    guards.remove(0)
+}
+
+#[test]
+fn example_patrol() {
+    let sim = single_guard(EXAMPLE);
+    let mut patrol = sim.clone();
+    assert_eq!(patrol.run(), &Outcome::Exited { step: 55 });
+    assert_eq!(patrol.visited().count(), 41);
+    let obstacles =
+        BTreeMap::from([(3, 6), (6, 7), (7, 7), (1, 8), (3, 8), (7, 9)].map(|o| (o, 0)));
+    let guards = [sim];
+    assert_eq!(find_loop_obstacles(&guards, NonZeroUsize::MIN), obstacles);
+    assert_eq!(
+        find_loop_obstacles(&guards, NonZeroUsize::new(4).unwrap()),
+        obstacles
+    );
+    assert_eq!(find_loop_obstacles_slow(&guards), obstacles);
+}
+
+#[test]
+fn renders_loop_cycle() {
+    let sim = single_guard(EXAMPLE);
+    let cycle = loop_cycle(&sim, (3, 6)).expect("obstacle to cause a loop");
+    assert_eq!(cycle.first(), Some(&((4, 6), Direction::Up)));
+    assert_eq!(
+        render_map(
+            std::slice::from_ref(&sim),
+            &BTreeSet::from([(3, 6)]),
+            &cycle
+        ),
+        "....#.....
+....+---+#
+....|...|.
+..#.|...|.
+....|..#|.
+....|...|.
+.#.O^---+.
+........#.
+#.........
+......#...
+"
+    );
+    assert_eq!(loop_cycle(&sim, (0, 0)), None);
+}
+
+#[test]
+fn animation_frames() {
+    let mut out = Vec::new();
+    animate(vec![single_guard(EXAMPLE)], &mut out, Duration::ZERO, false).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let frames: Vec<&str> = out.split("Move ").skip(1).collect();
+    // The starting map, then one frame per move
+    assert_eq!(frames.len(), 56);
+    assert_eq!(
+        frames[10],
+        "10
+....#.....
+....+--->#
+....|.....
+..#.|.....
+....|..#..
+....|.....
+.#..|.....
+........#.
+#.........
+......#...
+
+"
+    );
+    // The guard's gone by the last frame
+    assert!(!frames[55].contains(['^', '>', 'v', '<']));
+}
+
+#[test]
+fn step_by_step() {
+    let mut sim = single_guard(EXAMPLE);
+    assert_eq!(sim.position(), (4, 6));
+    // Five steps up to the obstacle, then turning on the spot
+    for _ in 0..6 {
+        assert_eq!(sim.step(), None);
+    }
+    assert_eq!(sim.position(), (4, 1));
+    assert_eq!(sim.direction(), Direction::Right);
+    let mut sim = single_guard("#.\n.#\n^.\n");
+    // Up into the corner, then right into the other obstacle
+    assert_eq!(sim.step(), None);
+    assert_eq!(sim.step(), None);
+    assert_eq!(sim.step(), None);
+    assert_eq!(sim.position(), (0, 1));
+    assert_eq!(sim.direction(), Direction::Down);
+    assert_eq!(sim.step(), None);
+    assert_eq!(sim.step(), Some(&Outcome::Exited { step: 5 }));
+}
+
+#[test]
+fn detects_loops() {
+    let mut sim = single_guard(".#..\n...#\n#^..\n..#.\n");
+    assert_eq!(sim.run(), &Outcome::Looped { step: 8 });
+}
+
+#[test]
+fn guard_rules() {
+    let rules = |turn, wrap| GuardRules { turn, wrap };
+    let run = |raw_data: &str, rules| {
+        let mut sim = parse_map(raw_data, rules).unwrap().remove(0);
+        let outcome = sim.run().clone();
+        (outcome, sim.position(), sim.direction())
+    };
+    let blocked = ".#.\n.^.\n...\n";
+    assert_eq!(
+        run(blocked, rules(Turn::CounterClockwise, false)),
+        (Outcome::Exited { step: 3 }, (0, 1), Direction::Left)
+    );
+    assert_eq!(
+        run(blocked, rules(Turn::Around, false)),
+        (Outcome::Exited { step: 3 }, (1, 2), Direction::Down)
+    );
+    // With nothing in the way, wrapping brings the guard straight back round
+    assert_eq!(
+        run("...\n.^.\n...\n", rules(Turn::Clockwise, true)),
+        (Outcome::Looped { step: 3 }, (1, 1), Direction::Up)
+    );
+    // Coming back in on the bottom edge and walking up into the obstacle
+    assert_eq!(
+        run(blocked, rules(Turn::Clockwise, true)).2,
+        Direction::Right
+    );
+    let boxed_in = ".#.\n#^#\n.#.\n";
+    assert_eq!(
+        run(boxed_in, rules(Turn::Clockwise, false)).0,
+        Outcome::Looped { step: 4 }
+    );
+    assert_eq!(
+        run(boxed_in, rules(Turn::Around, false)).0,
+        Outcome::Looped { step: 2 }
+    );
+}
+
+#[test]
+fn multiple_guards() {
+    let guards = parse_map("^.>\n...\nv.<\n", GuardRules::default()).unwrap();
+    let starts: Vec<_> = guards
+        .iter()
+        .map(|guard| (guard.position(), guard.direction()))
+        .collect();
+    assert_eq!(
+        starts,
+        [
+            ((0, 0), Direction::Up),
+            ((2, 0), Direction::Right),
+            ((0, 2), Direction::Down),
+            ((2, 2), Direction::Left),
+        ]
+    );
+    // A second guard standing where the first guard's loop obstacle would go
+    let guards = parse_map(
+        &EXAMPLE.replace(".#..^.....", ".#.<^....."),
+        GuardRules::default(),
+    )
+    .unwrap();
+    let obstacles = find_loop_obstacles(&guards, NonZeroUsize::MIN);
+    assert!(!obstacles.contains_key(&(3, 6)));
+    for obstacle in [(6, 7), (7, 7), (1, 8), (3, 8), (7, 9)] {
+        assert!(obstacles.contains_key(&obstacle));
+    }
+    assert_eq!(obstacles, find_loop_obstacles_slow(&guards));
+}
+
+#[test]
+fn map_shape() {
+    // Blank lines at the end are ignored
+    let guards = parse_map("....\n.^..\n....\n\n", GuardRules::default()).unwrap();
+    assert_eq!(guards[0].map.len(), 3);
+    assert_eq!(
+        find_loop_obstacles(&guards, NonZeroUsize::MIN),
+        find_loop_obstacles_slow(&guards)
+    );
+    assert_eq!(
+        parse_map("#...\n....\n.^\n", GuardRules::default()).err(),
+        Some("line 3 is 2 cells wide, but the map is 4 wide".to_owned())
+    );
+    assert!(parse_map("#...\n\n.^..\n", GuardRules::default()).is_err());
+    assert!(parse_map("#...\n....\n", GuardRules::default()).is_err());
+    assert!(parse_map("#.x.\n.^..\n", GuardRules::default()).is_err());
+}
+
+#[cfg(test)]
+fn random_map() -> impl proptest::strategy::Strategy<Value = String> {
+    use proptest::sample::select;
+    use proptest::strategy::Strategy;
+    let markers = vec!['^', '>', 'v', '<'];
+    (1..16_usize, 1..16_usize)
+        .prop_flat_map(move |(width, height)| {
+            (
+                proptest::collection::vec(
+                    select("..............####^>v<".chars().collect::<Vec<_>>()),
+                    width * height,
+                ),
+                // Make sure there's at least one guard
+                (0..width * height, select(markers.clone())),
+                proptest::strategy::Just(width),
+            )
+        })
+        .prop_map(|(mut cells, (start, marker), width)| {
+            cells[start] = marker;
+            let mut map = String::new();
+            for (i, cell) in cells.into_iter().enumerate() {
+                map.push(cell);
+                if i % width == width - 1 {
+                    map.push('\n');
+                }
+            }
+            map
+        })
+}
+
+#[cfg(test)]
+fn random_rules() -> impl proptest::strategy::Strategy<Value = GuardRules> {
+    use proptest::strategy::Strategy;
+    (
+        proptest::sample::select(vec![Turn::Clockwise, Turn::CounterClockwise, Turn::Around]),
+        proptest::bool::ANY,
+    )
+        .prop_map(|(turn, wrap)| GuardRules { turn, wrap })
+}
+
+proptest! {
+    #[test]
+    fn fast_loop_check_matches_slow(map in random_map(), rules in random_rules()) {
+        let guards = parse_map(&map, rules).unwrap();
+        let obstacles = find_loop_obstacles(&guards, NonZeroUsize::MIN);
+        for (&obstacle, &guard) in &obstacles {
+            assert!(loop_cycle(&guards[guard], obstacle).is_some());
+        }
+        assert_eq!(obstacles, find_loop_obstacles_slow(&guards));
+    }
+
+    #[test]
+    fn thread_count_doesnt_change_result(
+        map in random_map(),
+        rules in random_rules(),
+        threads in 2..8_usize,
+    ) {
+        let guards = parse_map(&map, rules).unwrap();
+        let threads = NonZeroUsize::new(threads).unwrap();
+        assert_eq!(
+            find_loop_obstacles(&guards, threads),
+            find_loop_obstacles(&guards, NonZeroUsize::MIN)
+        );
+    }
 }
@@ -1,13 +1,436 @@
-use crate::util::count_digits;
+use crate::util::{bench, flag_value, has_flag};
+use std::fmt::{self, Display};
 use std::fs;
+use std::ops::ControlFlow;
+use std::str::FromStr;
 
 use proptest::proptest;
 
+/// What equations can be made of: plain `i64`s, or `BigUint`s for numbers of any size.
+pub trait Number: Clone + PartialEq + Default + Display + FromStr {}
+
+impl<T: Clone + PartialEq + Default + Display + FromStr> Number for T {}
+
+/// Working backwards through an operator: what the left-hand number must have been to get some
+/// result from a given right-hand number.
 #[derive(Clone, Copy, PartialEq, Debug)]
-enum Operator {
-    Plus,
-    Times,
-    Concat,
+pub enum Inverse<N = i64> {
+    Left(N),
+    /// No left-hand number gives that result.
+    Impossible,
+    /// The operator can't be undone, so any left-hand number might work.
+    Unsupported,
+}
+
+/// How the operators in an equation are grouped.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Evaluation {
+    /// Strictly left to right, as the puzzle does it: `2 + 3 * 4` is 20.
+    #[default]
+    LeftToRight,
+    /// Higher precedence operators first, then left to right within a level: `2 + 3 * 4` is 14.
+    /// `^` is level 0, `+` and `-` 1, `*` and `/` 2 and `**` 3, and `||` goes at level `concat`.
+    /// As usual, `**` goes right to left instead, so `2 ** 3 ** 2` is 2 ** 9.
+    Precedence { concat: u8 },
+}
+
+/// Something that can go between two numbers in an equation. With `Evaluation::LeftToRight`,
+/// `left` is everything before the operator.
+pub trait Operator<N = i64> {
+    /// `left <op> right`, or `None` if it isn't defined for these numbers (e.g. dividing by 0) or
+    /// the result is too big for `N`.
+    fn apply(&self, left: &N, right: &N) -> Option<N>;
+    /// Works out `left` from `right` and the result of `left <op> right`.
+    fn inverse(&self, _result: &N, _right: &N) -> Inverse<N> {
+        Inverse::Unsupported
+    }
+    /// How the operator is written in an equation.
+    fn symbol(&self) -> &str;
+    /// How tightly the operator binds with `Evaluation::Precedence`, given the level picked for
+    /// concatenation. Binds like `+` unless overridden.
+    fn precedence(&self, _concat: u8) -> u8 {
+        1
+    }
+    /// Whether a run of this operator at the same level is worked out right to left with
+    /// `Evaluation::Precedence`.
+    fn right_associative(&self) -> bool {
+        false
+    }
+}
+
+pub struct Plus;
+pub struct Times;
+pub struct Concat;
+pub struct Minus;
+pub struct Divide;
+pub struct Power;
+pub struct Xor;
+
+impl Operator for Plus {
+    fn apply(&self, left: &i64, right: &i64) -> Option<i64> {
+        left.checked_add(*right)
+    }
+    fn inverse(&self, result: &i64, right: &i64) -> Inverse {
+        result
+            .checked_sub(*right)
+            .map_or(Inverse::Impossible, Inverse::Left)
+    }
+    fn symbol(&self) -> &str {
+        "+"
+    }
+}
+
+impl Operator for Times {
+    fn apply(&self, left: &i64, right: &i64) -> Option<i64> {
+        left.checked_mul(*right)
+    }
+    fn inverse(&self, result: &i64, right: &i64) -> Inverse {
+        match (
+            right,
+            result.checked_rem(*right),
+            result.checked_div(*right),
+        ) {
+            // Anything times 0 is 0
+            (0, _, _) if *result == 0 => Inverse::Unsupported,
+            (_, Some(0), Some(left)) => Inverse::Left(left),
+            _ => Inverse::Impossible,
+        }
+    }
+    fn symbol(&self) -> &str {
+        "*"
+    }
+    fn precedence(&self, _concat: u8) -> u8 {
+        2
+    }
+}
+
+impl Operator for Concat {
+    // A minus sign can't go in the middle of a number, but one on the left stays at the front,
+    // so -5 || 3 is -53
+    fn apply(&self, left: &i64, right: &i64) -> Option<i64> {
+        if *right < 0 {
+            return None;
+        }
+        if *left < 0 {
+            return number_concat(left.checked_neg()?, *right)?.checked_neg();
+        }
+        number_concat(*left, *right)
+    }
+    fn inverse(&self, result: &i64, right: &i64) -> Inverse {
+        // Nothing concatenates to i64::MIN, as its digits without the sign don't fit in an i64
+        let (true, Some(digits)) = (*right >= 0, result.checked_abs()) else {
+            return Inverse::Impossible;
+        };
+        // Strip the digits of `right` off the end of `result`, keeping its sign
+        match concat_shift(*right) {
+            Some(shift) if digits % shift == *right => {
+                let left = digits / shift;
+                if *result >= 0 {
+                    Inverse::Left(left)
+                } else if left == 0 {
+                    // A negative result needs a negative number on the left, and 0 isn't one
+                    Inverse::Impossible
+                } else {
+                    Inverse::Left(-left)
+                }
+            }
+            // Only 0 can have more digits than an i64 fits put after it
+            None if result == right => Inverse::Left(0),
+            _ => Inverse::Impossible,
+        }
+    }
+    fn symbol(&self) -> &str {
+        "||"
+    }
+    fn precedence(&self, concat: u8) -> u8 {
+        concat
+    }
+}
+
+impl Operator for Minus {
+    fn apply(&self, left: &i64, right: &i64) -> Option<i64> {
+        left.checked_sub(*right)
+    }
+    fn inverse(&self, result: &i64, right: &i64) -> Inverse {
+        result
+            .checked_add(*right)
+            .map_or(Inverse::Impossible, Inverse::Left)
+    }
+    fn symbol(&self) -> &str {
+        "-"
+    }
+}
+
+// Rounds towards 0, so more than one left-hand number gives the same result and it can't be
+// undone
+impl Operator for Divide {
+    fn apply(&self, left: &i64, right: &i64) -> Option<i64> {
+        left.checked_div(*right)
+    }
+    fn symbol(&self) -> &str {
+        "/"
+    }
+    fn precedence(&self, _concat: u8) -> u8 {
+        2
+    }
+}
+
+impl Operator for Power {
+    fn apply(&self, left: &i64, right: &i64) -> Option<i64> {
+        left.checked_pow(u32::try_from(*right).ok()?)
+    }
+    fn symbol(&self) -> &str {
+        "**"
+    }
+    fn precedence(&self, _concat: u8) -> u8 {
+        3
+    }
+    fn right_associative(&self) -> bool {
+        true
+    }
+}
+
+impl Operator for Xor {
+    fn apply(&self, left: &i64, right: &i64) -> Option<i64> {
+        Some(left ^ right)
+    }
+    fn inverse(&self, result: &i64, right: &i64) -> Inverse {
+        Inverse::Left(result ^ right)
+    }
+    fn symbol(&self) -> &str {
+        "^"
+    }
+    fn precedence(&self, _concat: u8) -> u8 {
+        0
+    }
+}
+
+/// A whole number of any size, for equations too big for an `i64`. Stored in base 10^9 so
+/// working with decimal digits is easy, least significant limb first and without any trailing
+/// zero limbs, so 0 has no limbs at all.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BigUint(Vec<u32>);
+
+const LIMB_BASE: u64 = 1_000_000_000;
+const LIMB_DIGITS: usize = 9;
+
+impl BigUint {
+    fn trimmed(mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        BigUint(limbs)
+    }
+
+    fn limb(&self, i: usize) -> u64 {
+        self.0.get(i).copied().map_or(0, u64::from)
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        let len = self.0.len().max(other.0.len());
+        let mut limbs = Vec::with_capacity(len + 1);
+        let mut carry = 0;
+        for i in 0..len {
+            let sum = self.limb(i) + other.limb(i) + carry;
+            limbs.push((sum % LIMB_BASE) as u32);
+            carry = sum / LIMB_BASE;
+        }
+        limbs.push(carry as u32);
+        Self::trimmed(limbs)
+    }
+
+    // `None` if `other` is bigger, as there are no negative numbers
+    fn sub(&self, other: &Self) -> Option<Self> {
+        let mut limbs = Vec::with_capacity(self.0.len());
+        let mut borrow = 0;
+        for i in 0..self.0.len().max(other.0.len()) {
+            let (diff, borrowed) = (self.limb(i) + LIMB_BASE)
+                .checked_sub(other.limb(i) + borrow)
+                .map(|diff| (diff % LIMB_BASE, diff < LIMB_BASE))
+                .expect("a limb plus the base to cover any limb and a borrow");
+            limbs.push(diff as u32);
+            borrow = u64::from(borrowed);
+        }
+        (borrow == 0).then(|| Self::trimmed(limbs))
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        let mut limbs = vec![0_u64; self.0.len() + other.0.len()];
+        for (i, &a) in self.0.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.0.iter().enumerate() {
+                let product = limbs[i + j] + u64::from(a) * u64::from(b) + carry;
+                limbs[i + j] = product % LIMB_BASE;
+                carry = product / LIMB_BASE;
+            }
+            limbs[i + other.0.len()] = carry;
+        }
+        Self::trimmed(limbs.into_iter().map(|limb| limb as u32).collect())
+    }
+
+    // The quotient and remainder of dividing by something that fits in a single limb
+    fn div_small(&self, divisor: u32) -> (Self, u32) {
+        let divisor = u64::from(divisor);
+        let mut limbs = vec![0; self.0.len()];
+        let mut remainder = 0;
+        for i in (0..self.0.len()).rev() {
+            let current = remainder * LIMB_BASE + u64::from(self.0[i]);
+            limbs[i] = (current / divisor) as u32;
+            remainder = current % divisor;
+        }
+        (Self::trimmed(limbs), remainder as u32)
+    }
+
+    fn digits(&self) -> usize {
+        match self.0.last() {
+            None => 1,
+            Some(top) => (self.0.len() - 1) * LIMB_DIGITS + top.to_string().len(),
+        }
+    }
+
+    // Puts `digits` zeros on the end
+    fn shift_digits(&self, digits: usize) -> Self {
+        let mut limbs = vec![0; digits / LIMB_DIGITS];
+        limbs.extend(&self.0);
+        Self::trimmed(limbs).mul(&BigUint::from(10_u64.pow((digits % LIMB_DIGITS) as u32)))
+    }
+
+    // Splits the last `digits` digits off, returning what's before them and then the digits
+    fn split_digits(&self, digits: usize) -> (Self, Self) {
+        let whole_limbs = (digits / LIMB_DIGITS).min(self.0.len());
+        let (low, high) = self.0.split_at(whole_limbs);
+        let (high, remainder) =
+            BigUint(high.to_vec()).div_small(10_u32.pow((digits % LIMB_DIGITS) as u32));
+        let mut low = low.to_vec();
+        low.push(remainder);
+        (high, Self::trimmed(low))
+    }
+}
+
+impl From<u64> for BigUint {
+    fn from(mut n: u64) -> Self {
+        let mut limbs = Vec::new();
+        while n > 0 {
+            limbs.push((n % LIMB_BASE) as u32);
+            n /= LIMB_BASE;
+        }
+        BigUint(limbs)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseBigUintError;
+
+impl FromStr for BigUint {
+    type Err = ParseBigUintError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseBigUintError);
+        }
+        // Each limb is 9 digits, counting from the end
+        let limbs = s
+            .as_bytes()
+            .rchunks(LIMB_DIGITS)
+            .map(|chunk| {
+                std::str::from_utf8(chunk)
+                    .expect("digits to be valid UTF-8")
+                    .parse()
+                    .expect("9 digits to fit in a limb")
+            })
+            .collect();
+        Ok(Self::trimmed(limbs))
+    }
+}
+
+impl Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some((top, rest)) = self.0.split_last() else {
+            return write!(f, "0");
+        };
+        write!(f, "{}", top)?;
+        for limb in rest.iter().rev() {
+            write!(f, "{:09}", limb)?;
+        }
+        Ok(())
+    }
+}
+
+impl Operator<BigUint> for Plus {
+    fn apply(&self, left: &BigUint, right: &BigUint) -> Option<BigUint> {
+        Some(left.add(right))
+    }
+    fn inverse(&self, result: &BigUint, right: &BigUint) -> Inverse<BigUint> {
+        result.sub(right).map_or(Inverse::Impossible, Inverse::Left)
+    }
+    fn symbol(&self) -> &str {
+        "+"
+    }
+}
+
+impl Operator<BigUint> for Times {
+    fn apply(&self, left: &BigUint, right: &BigUint) -> Option<BigUint> {
+        Some(left.mul(right))
+    }
+    fn inverse(&self, result: &BigUint, right: &BigUint) -> Inverse<BigUint> {
+        match right.0.as_slice() {
+            // Anything times 0 is 0
+            [] if result.0.is_empty() => Inverse::Unsupported,
+            [] => Inverse::Impossible,
+            &[divisor] => match result.div_small(divisor) {
+                (left, 0) => Inverse::Left(left),
+                _ => Inverse::Impossible,
+            },
+            // Only dividing by small numbers is supported, so try it forwards instead
+            _ => Inverse::Unsupported,
+        }
+    }
+    fn symbol(&self) -> &str {
+        "*"
+    }
+    fn precedence(&self, _concat: u8) -> u8 {
+        2
+    }
+}
+
+impl Operator<BigUint> for Concat {
+    fn apply(&self, left: &BigUint, right: &BigUint) -> Option<BigUint> {
+        Some(left.shift_digits(right.digits()).add(right))
+    }
+    fn inverse(&self, result: &BigUint, right: &BigUint) -> Inverse<BigUint> {
+        match result.split_digits(right.digits()) {
+            (left, end) if end == *right => Inverse::Left(left),
+            _ => Inverse::Impossible,
+        }
+    }
+    fn symbol(&self) -> &str {
+        "||"
+    }
+    fn precedence(&self, concat: u8) -> u8 {
+        concat
+    }
+}
+
+const PART_ONE: [&dyn Operator; 2] = [&Plus, &Times];
+const PART_TWO: [&dyn Operator; 3] = [&Plus, &Times, &Concat];
+// Every operator that can be picked by its symbol with `--operators`
+const ALL_OPERATORS: [&dyn Operator; 7] = [&Plus, &Times, &Concat, &Minus, &Divide, &Power, &Xor];
+// The operators that work with numbers of any size. The first two are the ones for part 1
+const BIG_OPERATORS: [&dyn Operator<BigUint>; 3] = [&Plus, &Times, &Concat];
+
+fn parse_operators<N>(
+    symbols: &str,
+    available: &[&'static dyn Operator<N>],
+) -> Vec<&'static dyn Operator<N>> {
+    symbols
+        .split(',')
+        .map(|symbol| {
+            *available
+                .iter()
+                .find(|operator| operator.symbol() == symbol.trim())
+                .unwrap_or_else(|| panic!("Unknown operator '{}'", symbol))
+        })
+        .collect()
 }
 
 fn next_operator<T: PartialEq + Copy>(cur: T, list: &[T]) -> Option<T> {
@@ -18,23 +441,27 @@ fn next_operator<T: PartialEq + Copy>(cur: T, list: &[T]) -> Option<T> {
     Some(list[idx])
 }
 
-fn generate_operator_permutations(
+fn generate_operator_permutations<T: PartialEq + Copy>(
     n: usize,
-    operators: &'_ [Operator],
-) -> impl Iterator<Item = Vec<Operator>> + '_ {
+    operators: &'_ [T],
+) -> impl Iterator<Item = Vec<T>> + '_ {
     let mut first_iter = true;
     let first_op = operators[0];
     let last_op = operators[operators.len() - 1];
     let mut current = vec![first_op; n];
 
     // Start at the right
-    let last_pos = n - 1;
+    let last_pos = n.saturating_sub(1);
     std::iter::from_fn(move || {
         // Don't modify the first iteration and just send it out
         if first_iter {
             first_iter = false;
             return Some(current.clone());
         }
+        // With no operators to change, the empty permutation was the only one
+        if n == 0 {
+            return None;
+        }
 
         if current[last_pos] == last_op {
             // If our current item is the last operator in the operators vector:
@@ -67,98 +494,783 @@ fn generate_operator_permutations(
     })
 }
 
-struct Equation {
-    test_value: i64,
-    numbers: Vec<i64>,
+struct Equation<N = i64> {
+    test_value: N,
+    numbers: Vec<N>,
+}
+
+fn parse_number<N: Number>(s: &str) -> N {
+    str::parse(s).unwrap_or_else(|_| {
+        panic!(
+            "Could not parse '{}' as a number (pass --big for numbers that don't fit in an i64)",
+            s
+        )
+    })
 }
 
-fn parse_raw_data(raw_data: &'_ str) -> impl Iterator<Item = Equation> + '_ {
+fn parse_raw_data<N: Number>(raw_data: &'_ str) -> impl Iterator<Item = Equation<N>> + '_ {
     raw_data
         .lines()
         .filter(|l| !l.is_empty())
         .filter_map(|l| l.split_once(':'))
         .map(|(test_val_string, numbers_string)| Equation {
-            test_value: str::parse(test_val_string).expect("test_val_string to be a valid i64"),
+            test_value: parse_number(test_val_string),
             numbers: numbers_string
                 .trim()
                 .split(' ')
-                .map(|num_str| str::parse(num_str).expect("all numbers after : to be valid"))
+                .map(parse_number)
                 .collect::<Vec<_>>(),
         })
 }
 
-fn number_concat(left: i64, right: i64) -> i64 {
-    let right_digits = count_digits(&(right as usize));
-    (left * 10_i64.pow(right_digits as u32)) + right
+// What the left-hand number of a concatenation gets multiplied by to make room for `right`'s
+// digits, or `None` if that's too big for an i64
+fn concat_shift(right: i64) -> Option<i64> {
+    let mut shift = 10_i64;
+    while shift <= right {
+        shift = shift.checked_mul(10)?;
+    }
+    Some(shift)
+}
+
+// `left || right`, e.g. 12 || 345 = 12345, or `None` if it's too big for an i64
+fn number_concat(left: i64, right: i64) -> Option<i64> {
+    match concat_shift(right) {
+        Some(shift) => left.checked_mul(shift)?.checked_add(right),
+        None if left == 0 => Some(right),
+        None => None,
+    }
 }
 
-fn test_equation(equation: &Equation, operators: &[Operator]) -> bool {
-    if equation.numbers.is_empty() {
+// Whether `left <op> right` gives `result`. Undoing the operator from the result is usually
+// cheaper than trying it, and lets the last operator be checked without being enumerated
+fn step_matches<N: Number>(operator: &dyn Operator<N>, left: &N, right: &N, result: &N) -> bool {
+    match operator.inverse(result, right) {
+        Inverse::Left(needed) => needed == *left,
+        Inverse::Impossible => false,
+        Inverse::Unsupported => operator.apply(left, right).as_ref() == Some(result),
+    }
+}
+
+// Tries every permutation of operators in turn. Kept to check and benchmark `test_equation`
+// against
+fn test_equation_enumerate<N: Number>(
+    equation: &Equation<N>,
+    operators: &[&dyn Operator<N>],
+) -> bool {
+    let Some((last, rest)) = equation.numbers.split_last() else {
         return false;
+    };
+    if rest.is_empty() {
+        return *last == equation.test_value;
     }
-    let mut op_permutations = generate_operator_permutations(equation.numbers.len() - 1, operators);
-    // Iterate over every permutation of len - 1 operators
+    // Permute indices into `operators`, as the operators themselves can't be compared
+    let indices: Vec<usize> = (0..operators.len()).collect();
+    let mut op_permutations = generate_operator_permutations(rest.len() - 1, &indices);
+    // Iterate over every permutation of the operators between all but the last number
     // return true as soon as we find any match
     op_permutations.any(|perm| {
-        equation
+        rest
             // Iterate over the numbers in the equation
-            .numbers
             .iter()
             // Skip the first number, as we'll use it as the init value when folding
             .skip(1)
             // Zip (combine) the number iterator with an iterator over the operators of this permutation
-            // Since we skipped the first value of `numbers`, these iterators should both be the same length
-            // (since perm.len() == numbers.len() - 1)
+            // Since we skipped the first value of `rest`, these iterators should both be the same length
+            // (since perm.len() == rest.len() - 1)
             .zip(perm)
             // Fold (reduce) by starting with the first number and then doing `acc = acc [operator] next` until we're out of iterations
-            .fold(equation.numbers[0], |acc, (n, operator)| match operator {
-                Operator::Plus => acc + n,
-                Operator::Times => acc * n,
-                Operator::Concat => number_concat(acc, *n),
+            // An operator that can't be applied means this permutation doesn't work
+            .try_fold(rest[0].clone(), |acc, (n, operator)| {
+                operators[operator].apply(&acc, n)
+            })
+            // Check whether any operator gets from the result of our fold to our test value
+            .is_some_and(|acc| {
+                operators
+                    .iter()
+                    .any(|operator| step_matches(*operator, &acc, last, &equation.test_value))
             })
-            // Compare the result of our fold with our test value
-            == equation.test_value
     })
 }
 
-pub fn solve(raw_data: &str) {
-    let total_sum_p1: i64 = parse_raw_data(raw_data)
-        .filter_map(
-            |eq| match test_equation(&eq, &[Operator::Plus, Operator::Times]) {
-                true => Some(eq.test_value),
-                false => None,
-            },
-        )
-        .sum();
-    println!(
-        "Total sum of valid equations for part 1 is {}",
-        total_sum_p1
-    );
-    let total_sum_p2: i64 = parse_raw_data(raw_data)
-        .filter_map(|eq| {
-            match test_equation(&eq, &[Operator::Plus, Operator::Times, Operator::Concat]) {
-                true => Some(eq.test_value),
-                false => None,
+// The operators picked for each gap between the numbers so far, as indices into the operator set
+type Chosen<'a> = &'a mut [usize];
+// Called with each complete set of operators found, and can stop the search early
+type Visit<'a> = &'a mut dyn FnMut(&[usize]) -> ControlFlow<()>;
+
+// Tries every way of putting operators between `numbers` from the left, filling in the first
+// `numbers.len() - 1` gaps of `chosen` and passing each result to `found`
+fn forward<N: Number>(
+    numbers: &[N],
+    operators: &[&dyn Operator<N>],
+    chosen: Chosen,
+    found: &mut dyn FnMut(&N, &[usize]) -> ControlFlow<()>,
+) -> ControlFlow<()> {
+    fn go<N: Number>(
+        acc: &N,
+        gap: usize,
+        numbers: &[N],
+        operators: &[&dyn Operator<N>],
+        chosen: Chosen,
+        found: &mut dyn FnMut(&N, &[usize]) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        let Some(next) = numbers.get(gap + 1) else {
+            return found(acc, chosen);
+        };
+        for (i, operator) in operators.iter().enumerate() {
+            if let Some(acc) = operator.apply(acc, next) {
+                chosen[gap] = i;
+                go(&acc, gap + 1, numbers, operators, chosen, found)?;
             }
-        })
-        .sum();
+        }
+        ControlFlow::Continue(())
+    }
+    match numbers.first() {
+        None => ControlFlow::Continue(()),
+        Some(first) => go(first, 0, numbers, operators, chosen, found),
+    }
+}
+
+// Works back from the target, undoing the last operator to find what everything before it has to
+// come to. Most operators can only be undone one way if at all (a product has to divide evenly,
+// a concatenation has to end in the right digits), which cuts off most branches straight away
+fn backward<N: Number>(
+    numbers: &[N],
+    target: &N,
+    operators: &[&dyn Operator<N>],
+    chosen: Chosen,
+    visit: Visit,
+) -> ControlFlow<()> {
+    let Some((last, rest)) = numbers.split_last() else {
+        return ControlFlow::Continue(());
+    };
+    if rest.is_empty() {
+        return if last == target {
+            visit(chosen)
+        } else {
+            ControlFlow::Continue(())
+        };
+    }
+    let gap = rest.len() - 1;
+    for (i, operator) in operators.iter().enumerate() {
+        chosen[gap] = i;
+        match operator.inverse(target, last) {
+            Inverse::Left(left) => backward(rest, &left, operators, chosen, visit)?,
+            Inverse::Impossible => {}
+            // No shortcut, so try everything the numbers before could come to
+            Inverse::Unsupported => forward(rest, operators, chosen, &mut |left, chosen| {
+                if operator.apply(left, last).as_ref() == Some(target) {
+                    visit(chosen)
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })?,
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+/// Works out `numbers` with the `chosen` operators between them, or `None` if any step isn't
+/// defined.
+fn evaluate<N: Number>(
+    numbers: &[N],
+    operators: &[&dyn Operator<N>],
+    chosen: &[usize],
+    evaluation: Evaluation,
+) -> Option<N> {
+    let (first, rest) = numbers.split_first()?;
+    let mut steps = rest.iter().zip(chosen).map(|(n, &i)| (n, operators[i]));
+    let Evaluation::Precedence { concat } = evaluation else {
+        return steps.try_fold(first.clone(), |acc, (n, operator)| operator.apply(&acc, n));
+    };
+    // Numbers wait on a stack until the next operator binds no tighter than the one before them
+    // (or, for right associative operators, less tightly)
+    let mut values = vec![first.clone()];
+    let mut pending: Vec<&dyn Operator<N>> = Vec::new();
+    let reduce = |values: &mut Vec<N>, operator: &dyn Operator<N>| {
+        let right = values.pop()?;
+        let left = values.pop()?;
+        values.push(operator.apply(&left, &right)?);
+        Some(())
+    };
+    for (n, operator) in steps {
+        while let Some(&before) = pending.last().filter(|before| {
+            let (before, next) = (before.precedence(concat), operator.precedence(concat));
+            before > next || (before == next && !operator.right_associative())
+        }) {
+            pending.pop();
+            reduce(&mut values, before)?;
+        }
+        pending.push(operator);
+        values.push(n.clone());
+    }
+    while let Some(operator) = pending.pop() {
+        reduce(&mut values, operator)?;
+    }
+    values.pop()
+}
+
+// Passes every set of operators that makes `equation` true to `visit`, until it breaks
+fn visit_solutions<N: Number>(
+    equation: &Equation<N>,
+    operators: &[&dyn Operator<N>],
+    evaluation: Evaluation,
+    visit: Visit,
+) {
+    let gaps = equation.numbers.len().saturating_sub(1);
+    if evaluation == Evaluation::LeftToRight {
+        let mut chosen = vec![0; gaps];
+        let _ = backward(
+            &equation.numbers,
+            &equation.test_value,
+            operators,
+            &mut chosen,
+            visit,
+        );
+        return;
+    }
+    // Undoing the last operator doesn't work when it might not be the last one worked out, so
+    // try every permutation instead
+    let indices: Vec<usize> = (0..operators.len()).collect();
+    for chosen in generate_operator_permutations(gaps, &indices) {
+        let value = evaluate(&equation.numbers, operators, &chosen, evaluation);
+        if value.as_ref() == Some(&equation.test_value) && visit(&chosen).is_break() {
+            return;
+        }
+    }
+}
+
+/// The operators (as indices into `operators`) that make `equation` true, stopping after
+/// `limit` of them if given.
+fn find_solutions<N: Number>(
+    equation: &Equation<N>,
+    operators: &[&dyn Operator<N>],
+    evaluation: Evaluation,
+    limit: Option<usize>,
+) -> Vec<Vec<usize>> {
+    let mut solutions = Vec::new();
+    visit_solutions(equation, operators, evaluation, &mut |chosen| {
+        solutions.push(chosen.to_vec());
+        if limit.is_some_and(|limit| solutions.len() >= limit) {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    solutions
+}
+
+fn count_solutions<N: Number>(
+    equation: &Equation<N>,
+    operators: &[&dyn Operator<N>],
+    evaluation: Evaluation,
+) -> usize {
+    let mut count = 0;
+    visit_solutions(equation, operators, evaluation, &mut |_| {
+        count += 1;
+        ControlFlow::Continue(())
+    });
+    count
+}
+
+fn test_equation<N: Number>(
+    equation: &Equation<N>,
+    operators: &[&dyn Operator<N>],
+    evaluation: Evaluation,
+) -> bool {
+    !find_solutions(equation, operators, evaluation, Some(1)).is_empty()
+}
+
+/// Writes out an equation with the operators from a solution, e.g. `81 + 40 * 27 = 3267`.
+fn render_solution<N: Number>(
+    equation: &Equation<N>,
+    operators: &[&dyn Operator<N>],
+    solution: &[usize],
+) -> String {
+    let mut out = equation.numbers[0].to_string();
+    for (number, &operator) in equation.numbers[1..].iter().zip(solution) {
+        out += &format!(" {} {}", operators[operator].symbol(), number);
+    }
+    out + &format!(" = {}", equation.test_value)
+}
+
+// Prints how each valid equation can be made true: just one way, every way, or how many ways
+fn show_solutions<N: Number>(
+    raw_data: &str,
+    operators: &[&dyn Operator<N>],
+    evaluation: Evaluation,
+    show: &str,
+) {
+    for equation in parse_raw_data::<N>(raw_data) {
+        match show {
+            "first" | "all" => {
+                let limit = (show == "first").then_some(1);
+                for solution in find_solutions(&equation, operators, evaluation, limit) {
+                    println!("{}", render_solution(&equation, operators, &solution));
+                }
+            }
+            "count" => {
+                let count = count_solutions(&equation, operators, evaluation);
+                if count > 0 {
+                    let plural = if count == 1 { "" } else { "s" };
+                    println!("{} has {} solution{}", equation.test_value, count, plural);
+                }
+            }
+            s => panic!("Unknown --show option '{}'", s),
+        }
+    }
+}
+
+fn bench_solvers(raw_data: &str) {
+    const RUNS: u32 = 10;
+    let equations: Vec<Equation> = parse_raw_data(raw_data).collect();
+    let count_valid =
+        |test: &dyn Fn(&Equation) -> bool| equations.iter().filter(|eq| test(eq)).count();
+    let backwards = bench(RUNS, || {
+        count_valid(&|eq| test_equation(eq, &PART_TWO, Evaluation::LeftToRight))
+    });
+    let enumerate = bench(RUNS, || {
+        count_valid(&|eq| test_equation_enumerate(eq, &PART_TWO))
+    });
     println!(
-        "Total sum of valid equations for part 2 is {}",
-        total_sum_p2
+        "Working backwards took {:?} per run, trying every permutation took {:?} ({:.1}x)",
+        backwards,
+        enumerate,
+        enumerate.as_secs_f64() / backwards.as_secs_f64()
     );
 }
 
+// The total of the test values of every valid equation, or `None` if it's too big for `N`
+fn sum_valid<N: Number>(
+    raw_data: &str,
+    operators: &[&dyn Operator<N>],
+    evaluation: Evaluation,
+) -> Option<N>
+where
+    Plus: Operator<N>,
+{
+    parse_raw_data::<N>(raw_data)
+        .filter(|eq| test_equation(eq, operators, evaluation))
+        .try_fold(N::default(), |total, eq| Plus.apply(&total, &eq.test_value))
+}
+
+fn print_total<N: Number>(description: &str, total: Option<N>) {
+    match total {
+        Some(total) => println!("Total sum of valid equations {} is {}", description, total),
+        None => println!(
+            "Total sum of valid equations {} is too big (pass --big to work it out)",
+            description
+        ),
+    }
+}
+
+fn solve_with<N: Number>(
+    raw_data: &str,
+    part_one: &[&dyn Operator<N>],
+    part_two: &[&'static dyn Operator<N>],
+    available: &[&'static dyn Operator<N>],
+) where
+    Plus: Operator<N>,
+{
+    // Pass `--precedence` to work out `*` before `+`, and `--concat-level N` to pick where `||`
+    // goes (see `Evaluation`). It binds tightest by default, like writing the digits together
+    let evaluation = if has_flag("--precedence") {
+        let concat = flag_value("--concat-level").map_or(4, |level| {
+            level
+                .parse()
+                .expect("--concat-level to be a number from 0 to 255")
+        });
+        Evaluation::Precedence { concat }
+    } else {
+        Evaluation::LeftToRight
+    };
+    print_total("for part 1", sum_valid(raw_data, part_one, evaluation));
+    print_total("for part 2", sum_valid(raw_data, part_two, evaluation));
+    // Pass `--operators +,*,||,-,/,**,^` to try a different set of operators
+    let operators = match flag_value("--operators") {
+        Some(symbols) => {
+            let operators = parse_operators(&symbols, available);
+            print_total(
+                &format!("using {}", symbols),
+                sum_valid(raw_data, &operators, evaluation),
+            );
+            operators
+        }
+        None => part_two.to_vec(),
+    };
+    // Pass `--show first|all|count` to see how the valid equations work out
+    if let Some(show) = flag_value("--show") {
+        show_solutions(raw_data, &operators, evaluation, &show);
+    }
+}
+
+pub fn solve(raw_data: &str) {
+    // Pass `--big` to work with numbers of any size. Otherwise anything that doesn't fit in an
+    // i64 along the way means that way of making the equation doesn't work
+    if has_flag("--big") {
+        solve_with(
+            raw_data,
+            &BIG_OPERATORS[..2],
+            &BIG_OPERATORS,
+            &BIG_OPERATORS,
+        );
+    } else {
+        solve_with(raw_data, &PART_ONE, &PART_TWO, &ALL_OPERATORS);
+    }
+    if has_flag("--bench") {
+        bench_solvers(raw_data);
+    }
+}
+
 pub fn solution() {
     let raw_data = fs::read_to_string("input/day7input.txt").expect("Failed to read input file!");
     solve(&raw_data);
 }
 
+#[cfg(test)]
+const EXAMPLE: &str = "190: 10 19
+3267: 81 40 27
+83: 17 5
+156: 15 6
+7290: 6 8 6 15
+161011: 16 10 13
+192: 17 8 14
+21037: 9 7 18 13
+292: 11 6 16 20
+";
+
+#[test]
+fn example_sums() {
+    assert_eq!(
+        sum_valid(EXAMPLE, &PART_ONE, Evaluation::LeftToRight),
+        Some(3749)
+    );
+    assert_eq!(
+        sum_valid(EXAMPLE, &PART_TWO, Evaluation::LeftToRight),
+        Some(11387)
+    );
+    assert_eq!(
+        sum_valid(EXAMPLE, &BIG_OPERATORS[..2], Evaluation::LeftToRight),
+        Some(BigUint::from(3749))
+    );
+    assert_eq!(
+        sum_valid(EXAMPLE, &BIG_OPERATORS, Evaluation::LeftToRight),
+        Some(BigUint::from(11387))
+    );
+}
+
+#[test]
+fn custom_operators() {
+    // Not one of ours, to check anything implementing the trait can be used
+    struct Max;
+    impl Operator for Max {
+        fn apply(&self, left: &i64, right: &i64) -> Option<i64> {
+            Some(*left.max(right))
+        }
+        fn symbol(&self) -> &str {
+            "max"
+        }
+    }
+    let works = |test_value, numbers: &[i64], operators: &[&dyn Operator]| {
+        let equation = Equation {
+            test_value,
+            numbers: numbers.to_vec(),
+        };
+        test_equation(&equation, operators, Evaluation::LeftToRight)
+    };
+    assert!(works(5, &[10, 5], &[&Minus]));
+    assert!(works(2, &[10, 5], &[&Plus, &Divide]));
+    assert!(!works(2, &[10, 0], &[&Divide]));
+    assert!(works(1000, &[10, 3], &[&Power]));
+    assert!(works(6, &[3, 5], &[&Xor]));
+    assert!(works(9, &[4, 9, 2], &[&Max]));
+    assert!(works(7, &[7], &[&Plus]));
+    assert!(works(-53, &[1, 6, 3], &[&Minus, &Concat]));
+    assert!(!works(-5, &[0, 5], &[&Concat]));
+    let operators = parse_operators("+, **", &ALL_OPERATORS);
+    assert_eq!(operators.len(), 2);
+    assert!(works(25, &[3, 2, 2], &operators));
+}
+
+#[test]
+fn example_solutions() {
+    let equations: Vec<Equation> = parse_raw_data(EXAMPLE).collect();
+    let rendered = |equation: &Equation, limit| -> Vec<String> {
+        find_solutions(equation, &PART_TWO, Evaluation::LeftToRight, limit)
+            .iter()
+            .map(|solution| render_solution(equation, &PART_TWO, solution))
+            .collect()
+    };
+    assert_eq!(rendered(&equations[0], None), ["10 * 19 = 190"]);
+    let mut both = rendered(&equations[1], None);
+    both.sort();
+    assert_eq!(both, ["81 * 40 + 27 = 3267", "81 + 40 * 27 = 3267"]);
+    assert_eq!(rendered(&equations[1], Some(1)).len(), 1);
+    assert_eq!(rendered(&equations[4], None), ["6 * 8 || 6 * 15 = 7290"]);
+    assert!(rendered(&equations[2], None).is_empty());
+    let counts: Vec<usize> = equations
+        .iter()
+        .map(|equation| count_solutions(equation, &PART_TWO, Evaluation::LeftToRight))
+        .collect();
+    assert_eq!(counts, [1, 2, 0, 1, 1, 0, 1, 0, 1]);
+}
+
+#[test]
+fn overflow_doesnt_match() {
+    // Wrapping round would make this 0
+    let equation = Equation {
+        test_value: 0,
+        numbers: vec![1 << 62, 4],
+    };
+    assert!(!test_equation(
+        &equation,
+        &PART_TWO,
+        Evaluation::LeftToRight
+    ));
+    assert!(!test_equation_enumerate(&equation, &PART_TWO));
+    let equation = Equation {
+        test_value: i64::MAX,
+        numbers: vec![i64::MAX, 1],
+    };
+    assert!(test_equation(&equation, &PART_ONE, Evaluation::LeftToRight));
+    // One more digit than fits in an i64
+    let equation = Equation {
+        test_value: 8,
+        numbers: vec![922337203685477580, 8],
+    };
+    assert_eq!(Concat.apply(&922337203685477580, &8), None);
+    // Nothing before a number that long still works
+    assert_eq!(number_concat(0, i64::MAX), Some(i64::MAX));
+    assert!(!test_equation(
+        &equation,
+        &PART_TWO,
+        Evaluation::LeftToRight
+    ));
+    let too_big =
+        "9223372036854775808: 922337203685477580 8\n18446744073709551616: 4294967296 4294967296\n";
+    // Both work out once the numbers can be big enough
+    let equations: Vec<Equation<BigUint>> = parse_raw_data(too_big).collect();
+    let rendered: Vec<String> = equations
+        .iter()
+        .map(|equation| {
+            let solutions = find_solutions(equation, &BIG_OPERATORS, Evaluation::LeftToRight, None);
+            assert_eq!(solutions.len(), 1);
+            render_solution(equation, &BIG_OPERATORS, &solutions[0])
+        })
+        .collect();
+    assert_eq!(
+        rendered,
+        [
+            "922337203685477580 || 8 = 9223372036854775808",
+            "4294967296 * 4294967296 = 18446744073709551616"
+        ]
+    );
+}
+
+#[test]
+fn precedence_evaluation() {
+    let numbers = [2, 3, 4];
+    let [plus, times] = [0, 1];
+    let left_to_right = evaluate(&numbers, &PART_ONE, &[plus, times], Evaluation::LeftToRight);
+    let precedence = Evaluation::Precedence { concat: 4 };
+    assert_eq!(left_to_right, Some(20));
+    assert_eq!(
+        evaluate(&numbers, &PART_ONE, &[plus, times], precedence),
+        Some(14)
+    );
+    // Both modes agree when the tighter operator comes first anyway
+    assert_eq!(
+        evaluate(&numbers, &PART_ONE, &[times, plus], precedence),
+        Some(10)
+    );
+    // Where `||` goes changes the answer too
+    let numbers = [6, 8, 6, 15];
+    let chosen = [1, 2, 1];
+    let evaluate_with = |evaluation| evaluate(&numbers, &PART_TWO, &chosen, evaluation);
+    assert_eq!(evaluate_with(Evaluation::LeftToRight), Some(7290));
+    assert_eq!(
+        evaluate_with(Evaluation::Precedence { concat: 4 }),
+        Some(7740)
+    );
+    assert_eq!(
+        evaluate_with(Evaluation::Precedence { concat: 0 }),
+        Some(4890)
+    );
+    // Powers go right to left
+    let powers = |evaluation| evaluate(&[2, 3, 2], &[&Power], &[0, 0], evaluation);
+    assert_eq!(powers(Evaluation::LeftToRight), Some(64));
+    assert_eq!(powers(precedence), Some(512));
+    let mixed: [&dyn Operator; 2] = [&Power, &Times];
+    assert_eq!(
+        evaluate(&[2, 3, 2, 2], &mixed, &[0, 0, 1], precedence),
+        Some(1024)
+    );
+    let operators: [&dyn Operator; 2] = [&Xor, &Power];
+    assert_eq!(
+        evaluate(&[2, 3, 2], &operators, &[0, 1], Evaluation::LeftToRight),
+        Some(1)
+    );
+    assert_eq!(
+        evaluate(&[2, 3, 2], &operators, &[0, 1], precedence),
+        Some(11)
+    );
+    // 292: 11 + 6 * 16 + 20 and 7290: 6 * 8 || 6 * 15 only work left to right, and so does
+    // 192: 17 || 8 + 14 once `||` binds looser than `+`
+    let sum = |operators: &[&dyn Operator], evaluation| sum_valid(EXAMPLE, operators, evaluation);
+    assert_eq!(sum(&PART_ONE, precedence), Some(3749 - 292));
+    assert_eq!(sum(&PART_TWO, precedence), Some(11387 - 292 - 7290));
+    assert_eq!(
+        sum(&PART_TWO, Evaluation::Precedence { concat: 0 }),
+        Some(11387 - 292 - 7290 - 192)
+    );
+    let equation = Equation {
+        test_value: 292,
+        numbers: vec![11, 6, 16, 20],
+    };
+    let rendered: Vec<String> = find_solutions(&equation, &PART_TWO, precedence, None)
+        .iter()
+        .map(|solution| render_solution(&equation, &PART_TWO, solution))
+        .collect();
+    assert_eq!(rendered, Vec::<String>::new());
+}
+
 proptest! {
+    #[test]
+    fn inverses_undo_operators(
+        left in proptest::prop_oneof![-1000..1000_i64, proptest::num::i64::ANY],
+        right in proptest::prop_oneof![-20..20_i64, proptest::num::i64::ANY],
+    ) {
+        // Concatenating really does put the digits next to each other, sign included
+        if let Some(result) = Concat.apply(&left, &right) {
+            assert_eq!(Some(result), format!("{}{}", left, right).parse().ok());
+        }
+        for operator in ALL_OPERATORS {
+            let Some(result) = operator.apply(&left, &right) else {
+                continue;
+            };
+            match operator.inverse(&result, &right) {
+                Inverse::Left(needed) => {
+                    assert_eq!(
+                        operator.apply(&needed, &right),
+                        Some(result),
+                        "{}",
+                        operator.symbol()
+                    );
+                }
+                Inverse::Impossible => panic!(
+                    "{} {} {} gave {} but can't be undone",
+                    left,
+                    operator.symbol(),
+                    right,
+                    result
+                ),
+                Inverse::Unsupported => {}
+            }
+        }
+    }
+
+    #[test]
+    fn backwards_matches_enumeration(
+        numbers in proptest::collection::vec(0..30_i64, 1..7),
+        random_value in 0..50000_i64,
+        operator_indices in proptest::collection::btree_set(0..ALL_OPERATORS.len(), 1..4),
+        picks in proptest::collection::vec(0..4_usize, 6),
+        reachable: bool,
+    ) {
+        let operators: Vec<&dyn Operator> =
+            operator_indices.into_iter().map(|i| ALL_OPERATORS[i]).collect();
+        // Half the time aim for a value one of the permutations actually comes to
+        let picked_value = numbers[1..]
+            .iter()
+            .zip(&picks)
+            .try_fold(numbers[0], |acc, (n, pick)| {
+                operators[pick % operators.len()].apply(&acc, n)
+            });
+        let test_value = match picked_value {
+            Some(value) if reachable => value,
+            _ => random_value,
+        };
+        let equation = Equation { test_value, numbers };
+        assert_eq!(
+            test_equation(&equation, &operators, Evaluation::LeftToRight),
+            test_equation_enumerate(&equation, &operators)
+        );
+        // Every solution found really works, and none are missed or found twice
+        let indices: Vec<usize> = (0..operators.len()).collect();
+        let working = generate_operator_permutations(equation.numbers.len() - 1, &indices)
+            .filter(|perm| {
+                equation.numbers[1..]
+                    .iter()
+                    .zip(perm)
+                    .try_fold(equation.numbers[0], |acc, (n, &i)| operators[i].apply(&acc, n))
+                    == Some(equation.test_value)
+            })
+            .count();
+        assert_eq!(count_solutions(&equation, &operators, Evaluation::LeftToRight), working);
+        let mut solutions = find_solutions(&equation, &operators, Evaluation::LeftToRight, None);
+        solutions.sort();
+        solutions.dedup();
+        assert_eq!(solutions.len(), working);
+    }
+
     #[test]
     fn test_num_concat(left in 0..10000000_i32, right in 0..10000000_i32) {
         let slow = str::parse::<i64>(&(left.to_string() + &right.to_string())).expect("slow string concat to work");
         assert_eq!(
-            number_concat(left.into(), right.into()), slow
+            number_concat(left.into(), right.into()), Some(slow)
         )
     }
+
+    #[test]
+    fn big_numbers_match_u128(left: u64, right: u64) {
+        let (big_left, big_right) = (BigUint::from(left), BigUint::from(right));
+        let (wide_left, wide_right) = (u128::from(left), u128::from(right));
+        assert_eq!(big_left.to_string(), left.to_string());
+        assert_eq!(left.to_string().parse(), Ok(big_left.clone()));
+        assert_eq!(big_left.add(&big_right).to_string(), (wide_left + wide_right).to_string());
+        assert_eq!(big_left.mul(&big_right).to_string(), (wide_left * wide_right).to_string());
+        assert_eq!(
+            big_left.sub(&big_right).map(|diff| diff.to_string()),
+            left.checked_sub(right).map(|diff| diff.to_string())
+        );
+        assert_eq!(big_left.digits(), left.to_string().len());
+        for operator in BIG_OPERATORS {
+            let result = operator.apply(&big_left, &big_right).unwrap();
+            match operator.inverse(&result, &big_right) {
+                Inverse::Left(needed) => assert_eq!(needed, big_left, "{}", operator.symbol()),
+                Inverse::Impossible => panic!("{} can't be undone", operator.symbol()),
+                Inverse::Unsupported => {}
+            }
+        }
+        let concat = Concat.apply(&big_left, &big_right).unwrap();
+        assert_eq!(Ok(concat), format!("{}{}", left, right).parse());
+    }
+
+    #[test]
+    fn precedence_matches_sum_of_products(
+        numbers in proptest::collection::vec(0..30_i64, 1..7),
+        times in proptest::collection::vec(proptest::bool::ANY, 6),
+    ) {
+        let chosen: Vec<usize> = times[..numbers.len() - 1]
+            .iter()
+            .map(|&t| usize::from(t))
+            .collect();
+        // Split into the terms being added up, each a product
+        let mut terms = vec![numbers[0]];
+        for (&n, &times) in numbers[1..].iter().zip(&times) {
+            if times {
+                *terms.last_mut().unwrap() *= n;
+            } else {
+                terms.push(n);
+            }
+        }
+        let precedence = Evaluation::Precedence { concat: 4 };
+        assert_eq!(evaluate(&numbers, &PART_ONE, &chosen, precedence), Some(terms.iter().sum()));
+        // With everything at the same level, it's just left to right
+        let same_level: [&dyn Operator; 2] = [&Plus, &Concat];
+        assert_eq!(
+            evaluate(&numbers, &same_level, &chosen, Evaluation::Precedence { concat: 1 }),
+            evaluate(&numbers, &same_level, &chosen, Evaluation::LeftToRight)
+        );
+    }
 }
@@ -1,7 +1,9 @@
+use crate::util::{flag_value, has_flag};
+use proptest::proptest;
 use std::{
     collections::{HashMap, HashSet},
     fs,
-    hash::{Hash, RandomState},
+    hash::Hash,
 };
 
 #[derive(Default, Eq, PartialEq, Clone, Copy, Debug, Hash)]
@@ -53,12 +55,48 @@ fn check_coords(
     }
 }
 
+/// Which points in line with a pair of antennas are antinodes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Antinodes {
+    /// Where one antenna is `ratio` times as far away as the other, which is 2 for part 1.
+    Ratio(usize),
+    /// Every point along the line, stepping by the offset between the antennas, as in part 2. If
+    /// `reduced`, the step is divided by the GCD of its coordinates so that every grid point in
+    /// line gets hit, including the ones between the antennas, not just multiples of the offset.
+    Resonant { reduced: bool },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct AntinodeRules {
+    antinodes: Antinodes,
+    /// Also count the antinodes between the two antennas, not just the ones beyond them. Only
+    /// affects [`Antinodes::Ratio`], as resonant antinodes already cover every step in between.
+    between: bool,
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// The offset divided by `divisor`, if it splits into whole grid steps
+fn divide_offset(offset_x: isize, offset_y: isize, divisor: usize) -> Option<(isize, isize)> {
+    let divisor = isize::try_from(divisor).ok().filter(|&d| d != 0)?;
+    if offset_x % divisor != 0 || offset_y % divisor != 0 {
+        return None;
+    }
+    Some((offset_x / divisor, offset_y / divisor))
+}
+
 fn process_coord_pair(
     left: &Coordinates,
     right: &Coordinates,
     xmax: usize,
     ymax: usize,
-    resonate: bool,
+    rules: &AntinodeRules,
 ) -> Vec<Coordinates> {
     let mut out_vec = Vec::<Coordinates>::new();
     // Get offset from the left coords to the right coords, converting to isize as we need negative
@@ -67,19 +105,53 @@ fn process_coord_pair(
         - isize::try_from(left.0).expect("usize -> isize conversion to always work");
     let offset_y = isize::try_from(right.1).expect("usize -> isize conversion to always work")
         - isize::try_from(left.1).expect("usize -> isize conversion to always work");
-    // Checked add the offset back onto the right coords
-    let mut out_x = right.0.checked_add_signed(offset_x);
-    let mut out_y = right.1.checked_add_signed(offset_y);
-    // Repeatedly add the offset to our current coordinates and check if they're valid, pushing
-    // them to our result vector each time
-    while let Some(coords) = check_coords(out_x, out_y, xmax, ymax) {
-        out_vec.push(coords);
-        // But for part 1, only do it once
-        if !resonate {
-            break;
+    // Checked add an offset onto some coords
+    let add = |coords: &Coordinates, (step_x, step_y): (isize, isize)| {
+        check_coords(
+            coords.0.checked_add_signed(step_x),
+            coords.1.checked_add_signed(step_y),
+            xmax,
+            ymax,
+        )
+    };
+    // Only the antinodes on the right's side are found here, as the pair also comes up the other
+    // way round
+    match rules.antinodes {
+        Antinodes::Ratio(ratio) => {
+            // Past the right coords, the left ones are `ratio` times as far away when we're
+            // another 1/(ratio - 1) of the offset along
+            let beyond = divide_offset(offset_x, offset_y, ratio - 1);
+            out_vec.extend(beyond.and_then(|step| add(right, step)));
+            // Between the two, the left coords are `ratio` times as far away when we're 1/(ratio +
+            // 1) of the offset back from the right ones
+            if rules.between {
+                let back = divide_offset(-offset_x, -offset_y, ratio + 1);
+                out_vec.extend(back.and_then(|step| add(right, step)));
+            }
+        }
+        Antinodes::Resonant { reduced } => {
+            let divisor = if reduced {
+                gcd(offset_x.unsigned_abs(), offset_y.unsigned_abs())
+            } else {
+                1
+            };
+            let step = divide_offset(offset_x, offset_y, divisor)
+                .expect("the GCD of the offset to divide it");
+            // Repeatedly add the step to our current coordinates and check if they're valid,
+            // pushing them to our result vector each time
+            let mut next = add(right, step);
+            while let Some(coords) = next {
+                out_vec.push(coords);
+                next = add(&coords, step);
+            }
+            // With a reduced step, there are grid points in between too
+            let back = (-step.0, -step.1);
+            let mut coords = *right;
+            for _ in 1..divisor {
+                coords = add(&coords, back).expect("points between antennas to be in bounds");
+                out_vec.push(coords);
+            }
         }
-        out_x = coords.0.checked_add_signed(offset_x);
-        out_y = coords.1.checked_add_signed(offset_y);
     }
     out_vec
 }
@@ -88,20 +160,20 @@ fn get_valid_pairs(
     map: &HashMap<char, Vec<Coordinates>>,
     ymax: usize,
     xmax: usize,
-    resonate: bool,
+    rules: &AntinodeRules,
 ) -> HashSet<Coordinates> {
     let nodes = map.values().flat_map(|coords_for_antenna| {
         all_pairs(coords_for_antenna)
-            .flat_map(|(left, right)| process_coord_pair(left, right, xmax, ymax, resonate))
+            .flat_map(|(left, right)| process_coord_pair(left, right, xmax, ymax, rules))
     });
-    if resonate {
+    if let Antinodes::Resonant { .. } = rules.antinodes {
         let antennas = map.values().flatten().copied();
         return HashSet::<Coordinates>::from_iter(antennas.chain(nodes));
     }
     HashSet::<Coordinates>::from_iter(nodes)
 }
 
-fn solve(raw_data: &str) {
+fn solve(raw_data: &str, between: bool, ratio: usize, reduced: bool) {
     let ymax = raw_data.lines().filter(|l| !l.is_empty()).count() - 1;
     let xmax = raw_data
         .lines()
@@ -110,12 +182,20 @@ fn solve(raw_data: &str) {
         .len()
         - 1;
     let antenna_map = ascii_to_antennas_map(raw_data);
-    let valid_pairs_1 = get_valid_pairs(&antenna_map, ymax, xmax, false);
+    let rules_1 = AntinodeRules {
+        antinodes: Antinodes::Ratio(ratio),
+        between,
+    };
+    let valid_pairs_1 = get_valid_pairs(&antenna_map, ymax, xmax, &rules_1);
     println!(
         "Number of unique antinode positions without resonation is {}",
         valid_pairs_1.len()
     );
-    let valid_pairs_2 = get_valid_pairs(&antenna_map, ymax, xmax, true);
+    let rules_2 = AntinodeRules {
+        antinodes: Antinodes::Resonant { reduced },
+        between,
+    };
+    let valid_pairs_2 = get_valid_pairs(&antenna_map, ymax, xmax, &rules_2);
     println!(
         "Number of unique antinode positions with resonation is {}",
         valid_pairs_2.len()
@@ -124,5 +204,114 @@ fn solve(raw_data: &str) {
 
 pub fn solution() {
     let raw_data = fs::read_to_string("input/day8input.txt").expect("Failed to read input file!");
-    solve(&raw_data);
+    // Pass `--ratio N` to look for antinodes where one antenna is N times as far away as the other
+    // instead of twice, `--between` to count the ones between antennas too, and `--reduce` to
+    // resonate at every grid point in line with a pair
+    let ratio = flag_value("--ratio").map_or(2, |ratio| {
+        ratio
+            .parse()
+            .ok()
+            .filter(|&ratio| ratio > 0)
+            .expect("--ratio to be a whole number above 0")
+    });
+    solve(
+        &raw_data,
+        has_flag("--between"),
+        ratio,
+        has_flag("--reduce"),
+    );
+}
+
+#[cfg(test)]
+const EXAMPLE: &str = "............
+........0...
+.....0......
+.......0....
+....0.......
+......A.....
+............
+............
+........A...
+.........A..
+............
+............
+";
+
+#[cfg(test)]
+fn antinodes(raw_data: &str, rules: AntinodeRules) -> HashSet<Coordinates> {
+    let ymax = raw_data.lines().filter(|l| !l.is_empty()).count() - 1;
+    let xmax = raw_data.lines().next().unwrap().len() - 1;
+    get_valid_pairs(&ascii_to_antennas_map(raw_data), ymax, xmax, &rules)
+}
+
+#[test]
+fn example_antinodes() {
+    let rules = |antinodes| AntinodeRules {
+        antinodes,
+        between: false,
+    };
+    assert_eq!(antinodes(EXAMPLE, rules(Antinodes::Ratio(2))).len(), 14);
+    let resonant = Antinodes::Resonant { reduced: false };
+    assert_eq!(antinodes(EXAMPLE, rules(resonant)).len(), 34);
+    // No pair in the example has a step that can be reduced
+    let reduced = Antinodes::Resonant { reduced: true };
+    assert_eq!(antinodes(EXAMPLE, rules(reduced)).len(), 34);
+}
+
+#[test]
+fn antinode_rules() {
+    // Two antennas 6 apart on a line 13 long
+    let line = "...a.....a...\n";
+    let xs = |antinodes, between| -> Vec<usize> {
+        let rules = AntinodeRules { antinodes, between };
+        let mut xs: Vec<usize> = self::antinodes(line, rules).iter().map(|c| c.0).collect();
+        xs.sort();
+        xs
+    };
+    assert_eq!(xs(Antinodes::Ratio(2), false), []);
+    assert_eq!(xs(Antinodes::Ratio(2), true), [5, 7]);
+    assert_eq!(xs(Antinodes::Ratio(3), false), [0, 12]);
+    assert_eq!(xs(Antinodes::Ratio(4), false), [1, 11]);
+    // 6 doesn't split into 4 whole steps, so only the ones between are on the grid
+    assert_eq!(xs(Antinodes::Ratio(5), true), [4, 8]);
+    // The midpoint is where both are the same distance away
+    assert_eq!(xs(Antinodes::Ratio(1), true), [6]);
+    let resonant = Antinodes::Resonant { reduced: false };
+    assert_eq!(xs(resonant, true), [3, 9]);
+    let reduced = Antinodes::Resonant { reduced: true };
+    assert_eq!(xs(reduced, false), (0..13).collect::<Vec<_>>());
+    assert_eq!(xs(reduced, true), (0..13).collect::<Vec<_>>());
+}
+
+proptest! {
+    #[test]
+    fn reduced_resonance_hits_every_point_in_line(
+        ax in 0..10_usize, ay in 0..10_usize, bx in 0..10_usize, by in 0..10_usize,
+    ) {
+        if (ax, ay) == (bx, by) {
+            return Ok(());
+        }
+        let mut grid = vec![vec!['.'; 10]; 10];
+        grid[ay][ax] = 'a';
+        grid[by][bx] = 'a';
+        let raw_data: String = grid
+            .iter()
+            .map(|row| row.iter().collect::<String>() + "\n")
+            .collect();
+        let rules = AntinodeRules {
+            antinodes: Antinodes::Resonant { reduced: true },
+            between: false,
+        };
+        let found = antinodes(&raw_data, rules);
+        // Every grid point on the line through the antennas
+        let in_line: HashSet<Coordinates> = (0..10)
+            .flat_map(|x| (0..10).map(move |y| Coordinates(x, y)))
+            .filter(|c| {
+                let cross = (c.0 as isize - ax as isize) * (by as isize - ay as isize)
+                    - (c.1 as isize - ay as isize) * (bx as isize - ax as isize);
+                cross == 0
+            })
+            .collect();
+        assert_eq!(found, in_line);
+    }
 }
@@ -1 +1,2 @@
-mod util;
+// Only util is declared here, so it has to be public for its helpers not to be dead code
+pub mod util;
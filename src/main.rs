@@ -12,27 +12,29 @@ mod day9;
 
 mod util;
 
+const SOLUTIONS: [fn(); 11] = [
+    day1::solution,
+    day2::solution,
+    day3::solution,
+    day4::solution,
+    day5::solution,
+    day6::solution,
+    day7::solution,
+    day8::solution,
+    day9::solution,
+    day10::solution,
+    day11::solution,
+];
+
 fn main() {
-    println!("Day 1:");
-    day1::solution();
-    println!("Day 2:");
-    day2::solution();
-    println!("Day 3:");
-    day3::solution();
-    println!("Day 4:");
-    day4::solution();
-    println!("Day 5:");
-    day5::solution();
-    println!("Day 6:");
-    day6::solution();
-    println!("Day 7:");
-    day7::solution();
-    println!("Day 8:");
-    day8::solution();
-    println!("Day 9:");
-    day9::solution();
-    println!("Day 10:");
-    day10::solution();
-    println!("Day 11:");
-    day11::solution();
+    // Pass `--day N` to only run a single day, which is also needed to pass it any other flags
+    let only_day = util::selected_day();
+    for (i, solution) in SOLUTIONS.iter().enumerate() {
+        let day = i + 1;
+        if only_day.is_some_and(|only| only != day) {
+            continue;
+        }
+        println!("Day {}:", day);
+        solution();
+    }
 }
@@ -1,3 +1,57 @@
+pub const ANSI_RESET: &str = "\x1b[0m";
+pub const ANSI_DIM: &str = "\x1b[2m";
+pub const ANSI_GREEN: &str = "\x1b[32m";
+pub const ANSI_RED: &str = "\x1b[31m";
+pub const ANSI_YELLOW: &str = "\x1b[33m";
+// Clears the terminal and moves the cursor back to the top left
+pub const ANSI_CLEAR: &str = "\x1b[2J\x1b[H";
+
 pub fn count_digits(num: &usize) -> usize {
     (*num as f64).log(10.0).trunc() as usize + 1
 }
+
+// Every day runs unless `--day N` picks one, and different days use the same flag names (day 4
+// and day 6 both have `--wrap`), so flags are only read when a single day is running
+
+/// Checks whether `flag` (e.g. `--trace`) was passed on the command line for the day picked
+/// with `--day N`.
+pub fn has_flag(flag: &str) -> bool {
+    selected_day().is_some() && std::env::args().any(|arg| arg == flag)
+}
+
+/// Gets the value passed for `flag` for the day picked with `--day N`, written as either
+/// `--flag value` or `--flag=value`.
+pub fn flag_value(flag: &str) -> Option<String> {
+    selected_day()?;
+    arg_value(flag)
+}
+
+/// The day picked with `--day N`, if only one day should run.
+pub fn selected_day() -> Option<usize> {
+    arg_value("--day").map(|day| str::parse(&day).expect("--day to be given a day number"))
+}
+
+fn arg_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args();
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+        if let Some(value) = arg
+            .strip_prefix(flag)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(value.to_owned());
+        }
+    }
+    None
+}
+
+/// Runs `f` `runs` times and returns the average time each run took.
+pub fn bench<T>(runs: u32, mut f: impl FnMut() -> T) -> std::time::Duration {
+    let start = std::time::Instant::now();
+    for _ in 0..runs {
+        std::hint::black_box(f());
+    }
+    start.elapsed() / runs
+}